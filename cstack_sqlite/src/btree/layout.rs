@@ -1,4 +1,7 @@
-use crate::{pager::PAGER_PAGE_SIZE, row::ROW_SIZE};
+//! On-page layout of tree nodes. The header fields sit at fixed offsets, while how many cells
+//! fit in a node depends on the page size of the database and, for leaves, on the size of
+//! their records.
+use crate::pager::usable_size;
 
 // Common Node Header Layout
 pub const NODE_TYPE_SIZE: usize = std::mem::size_of::<u8>();
@@ -6,19 +9,90 @@ pub const NODE_TYPE_OFFSET: usize = 0;
 pub const IS_ROOT_SIZE: usize = std::mem::size_of::<u8>();
 pub const IS_ROOT_OFFSET: usize = NODE_TYPE_SIZE;
 pub const PARENT_POINTER_SIZE: usize = std::mem::size_of::<u32>();
-pub const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_OFFSET;
+pub const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
 pub const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_POINTER_SIZE;
 
 // Leaf Node Header Layout
 pub const LEAF_NODE_NUM_CELLS_SIZE: usize = std::mem::size_of::<u32>();
 pub const LEAF_NODE_NUM_CELLS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
-pub const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE + LEAF_NODE_NUM_CELLS_SIZE;
+pub const LEAF_NODE_NEXT_LEAF_SIZE: usize = std::mem::size_of::<u32>();
+pub const LEAF_NODE_NEXT_LEAF_OFFSET: usize = LEAF_NODE_NUM_CELLS_OFFSET + LEAF_NODE_NUM_CELLS_SIZE;
+// offset of the first freeblock in the cell content area, 0 when there is none
+pub const LEAF_NODE_FIRST_FREEBLOCK_SIZE: usize = std::mem::size_of::<u16>();
+pub const LEAF_NODE_FIRST_FREEBLOCK_OFFSET: usize =
+    LEAF_NODE_NEXT_LEAF_OFFSET + LEAF_NODE_NEXT_LEAF_SIZE;
+// where the cell content area starts, 0 in a page never written to stands for its end
+pub const LEAF_NODE_CELL_CONTENT_START_SIZE: usize = std::mem::size_of::<u16>();
+pub const LEAF_NODE_CELL_CONTENT_START_OFFSET: usize =
+    LEAF_NODE_FIRST_FREEBLOCK_OFFSET + LEAF_NODE_FIRST_FREEBLOCK_SIZE;
+// free bytes in pieces too small to be freeblocks, reclaimed by defragmenting the node
+pub const LEAF_NODE_FRAGMENTED_BYTES_SIZE: usize = std::mem::size_of::<u8>();
+pub const LEAF_NODE_FRAGMENTED_BYTES_OFFSET: usize =
+    LEAF_NODE_CELL_CONTENT_START_OFFSET + LEAF_NODE_CELL_CONTENT_START_SIZE;
+pub const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE
+    + LEAF_NODE_NUM_CELLS_SIZE
+    + LEAF_NODE_NEXT_LEAF_SIZE
+    + LEAF_NODE_FIRST_FREEBLOCK_SIZE
+    + LEAF_NODE_CELL_CONTENT_START_SIZE
+    + LEAF_NODE_FRAGMENTED_BYTES_SIZE;
+/// Fragmented bytes a leaf can gather before it is defragmented.
+pub const LEAF_NODE_MAX_FRAGMENTED_BYTES: usize = 60;
 
 // Leaf Node Body Layout
+// the cell pointer array follows the header, with the offset of every cell in key order, and
+// grows towards the end of the page while cells are written from its end towards the start.
+// Each cell holds its key, the size of its record as a varint and the record itself.
+pub const LEAF_NODE_CELL_POINTER_SIZE: usize = std::mem::size_of::<u16>();
 pub const LEAF_NODE_KEY_SIZE: usize = std::mem::size_of::<u32>();
 pub const LEAF_NODE_KEY_OFFSET: usize = 0;
-pub const LEAF_NODE_VALUE_SIZE: usize = ROW_SIZE;
-pub const LEAF_NODE_VALUE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
-pub const LEAF_NODE_CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + LEAF_NODE_VALUE_SIZE;
-pub const LEAF_NODE_SPACE_FOR_CELLS: usize = PAGER_PAGE_SIZE - LEAF_NODE_HEADER_SIZE;
-pub const LEAF_NODE_MAX_CELLS: usize = LEAF_NODE_SPACE_FOR_CELLS / LEAF_NODE_CELL_SIZE;
+pub const LEAF_NODE_RECORD_SIZE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
+
+// Freeblock Layout
+// space freed between cells is kept as a list of freeblocks sorted by offset
+pub const FREEBLOCK_NEXT_SIZE: usize = std::mem::size_of::<u16>();
+pub const FREEBLOCK_NEXT_OFFSET: usize = 0;
+pub const FREEBLOCK_SIZE_SIZE: usize = std::mem::size_of::<u16>();
+pub const FREEBLOCK_SIZE_OFFSET: usize = FREEBLOCK_NEXT_OFFSET + FREEBLOCK_NEXT_SIZE;
+pub const FREEBLOCK_MIN_SIZE: usize = FREEBLOCK_NEXT_SIZE + FREEBLOCK_SIZE_SIZE;
+
+/// Bytes of a leaf node its cells and their pointers can take up.
+pub const fn leaf_node_capacity(page_size: usize) -> usize {
+    usable_size(page_size) - LEAF_NODE_HEADER_SIZE
+}
+
+/// Largest cell a leaf can hold. With its pointer it is half of a leaf, so a full leaf and a
+/// cell inserted into it can always be shared between two leaves.
+pub const fn leaf_node_max_cell_size(page_size: usize) -> usize {
+    leaf_node_capacity(page_size) / 2 - LEAF_NODE_CELL_POINTER_SIZE
+}
+
+/// A leaf whose cells and their pointers take up fewer bytes than this after a delete borrows
+/// from or merges with a sibling.
+pub const fn leaf_node_min_fill(page_size: usize) -> usize {
+    leaf_node_capacity(page_size) / 2
+}
+
+// Internal Node Header Layout
+pub const INTERNAL_NODE_NUM_KEYS_SIZE: usize = std::mem::size_of::<u32>();
+pub const INTERNAL_NODE_NUM_KEYS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+pub const INTERNAL_NODE_RIGHT_CHILD_SIZE: usize = std::mem::size_of::<u32>();
+pub const INTERNAL_NODE_RIGHT_CHILD_OFFSET: usize =
+    INTERNAL_NODE_NUM_KEYS_OFFSET + INTERNAL_NODE_NUM_KEYS_SIZE;
+pub const INTERNAL_NODE_HEADER_SIZE: usize =
+    COMMON_NODE_HEADER_SIZE + INTERNAL_NODE_NUM_KEYS_SIZE + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+
+// Internal Node Body Layout
+// each cell is a child page pointer followed by the largest key stored under that child
+pub const INTERNAL_NODE_CHILD_SIZE: usize = std::mem::size_of::<u32>();
+pub const INTERNAL_NODE_CHILD_OFFSET: usize = 0;
+pub const INTERNAL_NODE_KEY_SIZE: usize = std::mem::size_of::<u32>();
+pub const INTERNAL_NODE_KEY_OFFSET: usize = INTERNAL_NODE_CHILD_OFFSET + INTERNAL_NODE_CHILD_SIZE;
+pub const INTERNAL_NODE_CELL_SIZE: usize = INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE;
+
+pub const fn internal_node_max_cells(page_size: usize) -> usize {
+    (usable_size(page_size) - INTERNAL_NODE_HEADER_SIZE) / INTERNAL_NODE_CELL_SIZE
+}
+
+pub const fn internal_node_min_cells(page_size: usize) -> usize {
+    internal_node_max_cells(page_size) / 2
+}
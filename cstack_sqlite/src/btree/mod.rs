@@ -1,2 +1,2 @@
-mod layout;
+pub mod layout;
 pub mod node;
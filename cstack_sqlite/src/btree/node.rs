@@ -1,18 +1,56 @@
 use crate::{
-    cursor::Cursor,
+    page::{read_u16, read_u32, write_u16, write_u32},
+    pager::usable_size,
     row::{Row, RowSerializationError},
-    table::Table,
+    schema::Schema,
+    varint,
 };
 
 use super::layout::{
-    LEAF_NODE_CELL_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_KEY_OFFSET, LEAF_NODE_KEY_SIZE,
-    LEAF_NODE_MAX_CELLS, LEAF_NODE_NUM_CELLS_OFFSET, LEAF_NODE_VALUE_OFFSET, LEAF_NODE_VALUE_SIZE,
-    NODE_TYPE_SIZE,
+    leaf_node_capacity, leaf_node_max_cell_size, FREEBLOCK_MIN_SIZE, FREEBLOCK_NEXT_OFFSET,
+    FREEBLOCK_SIZE_OFFSET, INTERNAL_NODE_CELL_SIZE, INTERNAL_NODE_CHILD_OFFSET,
+    INTERNAL_NODE_HEADER_SIZE, INTERNAL_NODE_KEY_OFFSET, INTERNAL_NODE_NUM_KEYS_OFFSET,
+    INTERNAL_NODE_RIGHT_CHILD_OFFSET, IS_ROOT_OFFSET, LEAF_NODE_CELL_CONTENT_START_OFFSET,
+    LEAF_NODE_CELL_POINTER_SIZE, LEAF_NODE_FIRST_FREEBLOCK_OFFSET,
+    LEAF_NODE_FRAGMENTED_BYTES_OFFSET, LEAF_NODE_HEADER_SIZE, LEAF_NODE_KEY_OFFSET,
+    LEAF_NODE_KEY_SIZE, LEAF_NODE_MAX_FRAGMENTED_BYTES, LEAF_NODE_NEXT_LEAF_OFFSET,
+    LEAF_NODE_NUM_CELLS_OFFSET, LEAF_NODE_RECORD_SIZE_OFFSET, NODE_TYPE_OFFSET, NODE_TYPE_SIZE,
+    PARENT_POINTER_OFFSET,
 };
 
+const LEAF_NODE_TYPE: u8 = 0;
+const INTERNAL_NODE_TYPE: u8 = 1;
+
 pub enum NodeError {
-    NodeFullInsertError,
     SerializationError(RowSerializationError),
+    CellTooLarge { size: usize, max_size: usize },
+}
+
+/// Serializes a key and its row, laid out by `schema`, into the on-page representation of a
+/// leaf cell, which has to fit in a leaf of a database with `page_size` pages.
+pub fn leaf_cell(
+    key: u32,
+    value: &Row,
+    schema: &Schema,
+    page_size: usize,
+) -> Result<Vec<u8>, NodeError> {
+    let record = value
+        .serialize(schema)
+        .map_err(NodeError::SerializationError)?;
+
+    let mut cell = key.to_be_bytes().to_vec();
+    varint::write(&mut cell, record.len() as u64);
+    cell.extend(record);
+
+    let max_size = leaf_node_max_cell_size(page_size);
+    if cell.len() > max_size {
+        return Err(NodeError::CellTooLarge {
+            size: cell.len(),
+            max_size,
+        });
+    }
+
+    Ok(cell)
 }
 
 pub struct LeafNode {
@@ -21,17 +59,307 @@ pub struct LeafNode {
 
 impl LeafNode {
     fn cell_count(&self) -> usize {
-        let bytes: [u8; 4] = self.raw[LEAF_NODE_NUM_CELLS_OFFSET..LEAF_NODE_HEADER_SIZE]
-            .try_into()
-            .expect("invalid num cells");
+        read_u32(&self.raw, LEAF_NODE_NUM_CELLS_OFFSET) as usize
+    }
+
+    fn set_cell_count(&mut self, count: usize) {
+        write_u32(&mut self.raw, LEAF_NODE_NUM_CELLS_OFFSET, count as u32)
+    }
+
+    /// Page number of the sibling leaf to the right, 0 means this is the rightmost leaf.
+    /// Page 0 is the header page, which is never a tree node, so it can never be anyone's
+    /// sibling.
+    fn next_leaf(&self) -> usize {
+        read_u32(&self.raw, LEAF_NODE_NEXT_LEAF_OFFSET) as usize
+    }
+
+    fn set_next_leaf(&mut self, page_num: usize) {
+        write_u32(&mut self.raw, LEAF_NODE_NEXT_LEAF_OFFSET, page_num as u32)
+    }
 
-        u32::from_be_bytes(bytes) as usize
+    fn first_freeblock(&self) -> usize {
+        read_u16(&self.raw, LEAF_NODE_FIRST_FREEBLOCK_OFFSET) as usize
+    }
+
+    fn set_first_freeblock(&mut self, offset: usize) {
+        write_u16(
+            &mut self.raw,
+            LEAF_NODE_FIRST_FREEBLOCK_OFFSET,
+            offset as u16,
+        )
+    }
+
+    fn cell_content_start(&self) -> usize {
+        match read_u16(&self.raw, LEAF_NODE_CELL_CONTENT_START_OFFSET) as usize {
+            0 => usable_size(self.raw.len()),
+            start => start,
+        }
+    }
+
+    fn set_cell_content_start(&mut self, start: usize) {
+        write_u16(
+            &mut self.raw,
+            LEAF_NODE_CELL_CONTENT_START_OFFSET,
+            start as u16,
+        )
+    }
+
+    fn fragmented_bytes(&self) -> usize {
+        self.raw[LEAF_NODE_FRAGMENTED_BYTES_OFFSET] as usize
+    }
+
+    fn set_fragmented_bytes(&mut self, count: usize) {
+        self.raw[LEAF_NODE_FRAGMENTED_BYTES_OFFSET] = count as u8;
+    }
+
+    fn cell_pointer_offset(cell_num: usize) -> usize {
+        LEAF_NODE_HEADER_SIZE + cell_num * LEAF_NODE_CELL_POINTER_SIZE
+    }
+
+    fn cell_offset(&self, cell_num: usize) -> usize {
+        read_u16(&self.raw, Self::cell_pointer_offset(cell_num)) as usize
+    }
+
+    fn set_cell_offset(&mut self, cell_num: usize, offset: usize) {
+        write_u16(
+            &mut self.raw,
+            Self::cell_pointer_offset(cell_num),
+            offset as u16,
+        )
+    }
+
+    /// Size of the cell starting at `offset`, read from the record size it starts with.
+    fn cell_size_at(&self, offset: usize) -> usize {
+        let (record_size, len) = varint::read(&self.raw[(offset + LEAF_NODE_RECORD_SIZE_OFFSET)..])
+            .expect("leaf cell has an invalid record size");
+
+        LEAF_NODE_KEY_SIZE + len + record_size as usize
     }
 
     fn get_cell(&mut self, cell_num: usize) -> &mut [u8] {
-        let cell_offset = cell_num * LEAF_NODE_CELL_SIZE;
-        let offset = LEAF_NODE_HEADER_SIZE + cell_offset;
-        &mut self.raw[offset..(offset + LEAF_NODE_CELL_SIZE)]
+        let offset = self.cell_offset(cell_num);
+        let size = self.cell_size_at(offset);
+        &mut self.raw[offset..(offset + size)]
+    }
+
+    fn cell(&self, cell_num: usize) -> &[u8] {
+        let offset = self.cell_offset(cell_num);
+        &self.raw[offset..(offset + self.cell_size_at(offset))]
+    }
+
+    fn key(&self, cell_num: usize) -> u32 {
+        read_u32(self.cell(cell_num), LEAF_NODE_KEY_OFFSET)
+    }
+
+    fn record(&self, cell_num: usize) -> &[u8] {
+        let cell = self.cell(cell_num);
+        let (_, len) = varint::read(&cell[LEAF_NODE_RECORD_SIZE_OFFSET..])
+            .expect("leaf cell has an invalid record size");
+
+        &cell[(LEAF_NODE_RECORD_SIZE_OFFSET + len)..]
+    }
+
+    /// Offset and size of every freeblock, in the order of the list.
+    fn freeblocks(&self) -> Vec<(usize, usize)> {
+        let mut freeblocks = vec![];
+        let mut offset = self.first_freeblock();
+
+        while offset != 0 {
+            let size = read_u16(&self.raw, offset + FREEBLOCK_SIZE_OFFSET) as usize;
+            freeblocks.push((offset, size));
+            offset = read_u16(&self.raw, offset + FREEBLOCK_NEXT_OFFSET) as usize;
+        }
+
+        freeblocks
+    }
+
+    /// Links `freeblocks`, which are sorted by offset, into the freeblock list.
+    fn set_freeblocks(&mut self, freeblocks: &[(usize, usize)]) {
+        self.set_first_freeblock(freeblocks.first().map_or(0, |(offset, _)| *offset));
+
+        for (i, (offset, size)) in freeblocks.iter().enumerate() {
+            let next = freeblocks.get(i + 1).map_or(0, |(offset, _)| *offset);
+            write_u16(&mut self.raw, offset + FREEBLOCK_NEXT_OFFSET, next as u16);
+            write_u16(&mut self.raw, offset + FREEBLOCK_SIZE_OFFSET, *size as u16);
+        }
+    }
+
+    /// Bytes between the end of the cell pointer array and the start of the cell content.
+    fn gap(&self) -> usize {
+        self.cell_content_start() - Self::cell_pointer_offset(self.cell_count())
+    }
+
+    fn free_space(&self) -> usize {
+        let freeblocks: usize = self.freeblocks().iter().map(|(_, size)| size).sum();
+        self.gap() + freeblocks + self.fragmented_bytes()
+    }
+
+    /// Bytes taken up by the cells and their pointers.
+    fn used_space(&self) -> usize {
+        leaf_node_capacity(self.raw.len()) - self.free_space()
+    }
+
+    /// Finds room for `size` bytes of cell content in the first freeblock large enough, or
+    /// else at the start of the content area, defragmenting the node when the gap is too
+    /// small. Room for the cell's pointer has to be left in the gap already.
+    fn allocate(&mut self, size: usize) -> usize {
+        let mut freeblocks = self.freeblocks();
+
+        if let Some(i) = freeblocks.iter().position(|(_, free)| *free >= size) {
+            let (offset, free) = freeblocks[i];
+            let left = free - size;
+            if left < FREEBLOCK_MIN_SIZE {
+                freeblocks.remove(i);
+                self.set_fragmented_bytes(self.fragmented_bytes() + left);
+            } else {
+                freeblocks[i].1 = left;
+            }
+            self.set_freeblocks(&freeblocks);
+
+            // the cell takes the end of the block, so what is left of it stays in place
+            return offset + left;
+        }
+
+        if self.gap() < size + LEAF_NODE_CELL_POINTER_SIZE {
+            self.defragment();
+        }
+        let start = self.cell_content_start() - size;
+        self.set_cell_content_start(start);
+
+        start
+    }
+
+    /// Returns the `size` bytes at `offset` to the freeblock list, merged with any freeblock
+    /// they touch. A freeblock that ends up at the start of the content area is given back
+    /// to the gap instead.
+    fn free(&mut self, offset: usize, size: usize) {
+        self.raw[offset..(offset + size)].fill(0);
+
+        let mut freeblocks = self.freeblocks();
+        let mut i = freeblocks.partition_point(|(free_offset, _)| *free_offset < offset);
+        freeblocks.insert(i, (offset, size));
+
+        if i + 1 < freeblocks.len() && offset + size == freeblocks[i + 1].0 {
+            freeblocks[i].1 += freeblocks.remove(i + 1).1;
+        }
+        if i > 0 && freeblocks[i - 1].0 + freeblocks[i - 1].1 == offset {
+            freeblocks[i - 1].1 += freeblocks.remove(i).1;
+            i -= 1;
+        }
+
+        // every cell is at least as large as a freeblock header, so no fragment is left
+        let (free_offset, free_size) = freeblocks[i];
+        if free_offset == self.cell_content_start() {
+            freeblocks.remove(i);
+            self.raw[free_offset..(free_offset + free_size)].fill(0);
+            self.set_cell_content_start(free_offset + free_size);
+        }
+        self.set_freeblocks(&freeblocks);
+    }
+
+    /// Moves every cell to the end of the page, so all free space is in the gap again.
+    fn defragment(&mut self) {
+        let cells = self.cells();
+        self.set_cells(&cells);
+    }
+
+    /// Binary searches the cells for `key`, returning the cell that holds it or the
+    /// position it would have to be inserted at to keep the cells sorted.
+    fn find(&self, key: u32) -> usize {
+        let mut min_index = 0;
+        let mut one_past_max_index = self.cell_count();
+
+        while one_past_max_index != min_index {
+            let index = (min_index + one_past_max_index) / 2;
+            let key_at_index = self.key(index);
+
+            if key == key_at_index {
+                return index;
+            }
+
+            if key < key_at_index {
+                one_past_max_index = index;
+            } else {
+                min_index = index + 1;
+            }
+        }
+
+        min_index
+    }
+
+    /// Writes `cell` wherever there is room for it, so only the pointers after its position
+    /// move.
+    fn insert_cell(&mut self, cell_num: usize, cell: &[u8]) {
+        assert!(
+            cell.len() + LEAF_NODE_CELL_POINTER_SIZE <= self.free_space(),
+            "leaf node is full"
+        );
+        if self.gap() < LEAF_NODE_CELL_POINTER_SIZE
+            || self.fragmented_bytes() > LEAF_NODE_MAX_FRAGMENTED_BYTES
+        {
+            self.defragment();
+        }
+
+        let offset = self.allocate(cell.len());
+        self.raw[offset..(offset + cell.len())].copy_from_slice(cell);
+
+        // Make room for new cell pointer
+        let cell_count = self.cell_count();
+        let start = Self::cell_pointer_offset(cell_num);
+        let end = Self::cell_pointer_offset(cell_count);
+        self.raw
+            .copy_within(start..end, start + LEAF_NODE_CELL_POINTER_SIZE);
+
+        self.set_cell_offset(cell_num, offset);
+        self.set_cell_count(cell_count + 1);
+    }
+
+    fn remove_cell(&mut self, cell_num: usize) {
+        let cell_count = self.cell_count();
+        let offset = self.cell_offset(cell_num);
+        let size = self.cell_size_at(offset);
+
+        // Close the gap left by the removed cell pointer
+        let start = Self::cell_pointer_offset(cell_num + 1);
+        let end = Self::cell_pointer_offset(cell_count);
+        self.raw
+            .copy_within(start..end, start - LEAF_NODE_CELL_POINTER_SIZE);
+        self.raw[(end - LEAF_NODE_CELL_POINTER_SIZE)..end].fill(0);
+        self.set_cell_count(cell_count - 1);
+
+        self.free(offset, size);
+    }
+
+    fn cells(&self) -> Vec<Vec<u8>> {
+        (0..self.cell_count())
+            .map(|cell_num| self.cell(cell_num).to_vec())
+            .collect()
+    }
+
+    /// Replaces the cells with `cells`, packed at the end of the page with no free space
+    /// between them.
+    fn set_cells(&mut self, cells: &[Vec<u8>]) {
+        let mut content_start = usable_size(self.raw.len());
+
+        for (cell_num, cell) in cells.iter().enumerate() {
+            content_start = content_start
+                .checked_sub(cell.len())
+                .expect("cells do not fit in a leaf node");
+            self.raw[content_start..(content_start + cell.len())].copy_from_slice(cell);
+            self.set_cell_offset(cell_num, content_start);
+        }
+
+        let pointers_end = Self::cell_pointer_offset(cells.len());
+        assert!(
+            pointers_end <= content_start,
+            "cells do not fit in a leaf node"
+        );
+        self.raw[pointers_end..content_start].fill(0);
+
+        self.set_cell_count(cells.len());
+        self.set_cell_content_start(content_start);
+        self.set_first_freeblock(0);
+        self.set_fragmented_bytes(0);
     }
 }
 
@@ -39,6 +367,101 @@ pub struct InternalNode {
     raw: Vec<u8>,
 }
 
+impl InternalNode {
+    fn key_count(&self) -> usize {
+        read_u32(&self.raw, INTERNAL_NODE_NUM_KEYS_OFFSET) as usize
+    }
+
+    fn right_child(&self) -> usize {
+        read_u32(&self.raw, INTERNAL_NODE_RIGHT_CHILD_OFFSET) as usize
+    }
+
+    fn cell_offset(cell_num: usize) -> usize {
+        INTERNAL_NODE_HEADER_SIZE + cell_num * INTERNAL_NODE_CELL_SIZE
+    }
+
+    /// Child `key_count()` is the right child, every other child lives in a cell.
+    fn child(&self, child_num: usize) -> usize {
+        let key_count = self.key_count();
+        assert!(
+            child_num <= key_count,
+            "tried to access child_num {child_num} > key_count {key_count}"
+        );
+
+        if child_num == key_count {
+            return self.right_child();
+        }
+
+        read_u32(
+            &self.raw,
+            Self::cell_offset(child_num) + INTERNAL_NODE_CHILD_OFFSET,
+        ) as usize
+    }
+
+    fn key(&self, key_num: usize) -> u32 {
+        read_u32(
+            &self.raw,
+            Self::cell_offset(key_num) + INTERNAL_NODE_KEY_OFFSET,
+        )
+    }
+
+    /// Binary searches the keys for the child whose subtree should contain `key`. Each key
+    /// is the largest key under its child, so keys past the last one go to the right child.
+    fn find_child(&self, key: u32) -> usize {
+        let mut min_index = 0;
+        let mut max_index = self.key_count(); // there is one more child than key
+
+        while min_index != max_index {
+            let index = (min_index + max_index) / 2;
+
+            if self.key(index) >= key {
+                max_index = index;
+            } else {
+                min_index = index + 1;
+            }
+        }
+
+        self.child(min_index)
+    }
+
+    fn children(&self) -> Vec<usize> {
+        (0..=self.key_count()).map(|i| self.child(i)).collect()
+    }
+
+    fn keys(&self) -> Vec<u32> {
+        (0..self.key_count()).map(|i| self.key(i)).collect()
+    }
+
+    fn set_entries(&mut self, children: &[usize], keys: &[u32]) {
+        assert_eq!(
+            children.len(),
+            keys.len() + 1,
+            "internal node needs one more child than keys"
+        );
+
+        for (i, key) in keys.iter().enumerate() {
+            let offset = Self::cell_offset(i);
+            write_u32(
+                &mut self.raw,
+                offset + INTERNAL_NODE_CHILD_OFFSET,
+                children[i] as u32,
+            );
+            write_u32(&mut self.raw, offset + INTERNAL_NODE_KEY_OFFSET, *key);
+        }
+
+        write_u32(
+            &mut self.raw,
+            INTERNAL_NODE_NUM_KEYS_OFFSET,
+            keys.len() as u32,
+        );
+        write_u32(
+            &mut self.raw,
+            INTERNAL_NODE_RIGHT_CHILD_OFFSET,
+            children[keys.len()] as u32,
+        );
+    }
+}
+
 pub enum Node {
     Leaf(LeafNode),
     Internal(InternalNode),
@@ -58,12 +481,12 @@ impl From<Vec<u8>> for InternalNode {
 
 impl From<Vec<u8>> for Node {
     fn from(page: Vec<u8>) -> Self {
-        // first byte is either 0 [internal node] or 1 [leaf node]
+        // first byte is either 0 [leaf node] or 1 [internal node]
         let node_type: [u8; NODE_TYPE_SIZE] = page[..NODE_TYPE_SIZE]
             .try_into()
             .expect("failed to extract node_type");
         let node_type = u8::from_be_bytes(node_type);
-        if node_type == 0 {
+        if node_type == LEAF_NODE_TYPE {
             Self::Leaf(LeafNode::from(page))
         } else {
             Self::Internal(InternalNode::from(page))
@@ -72,6 +495,18 @@ impl From<Vec<u8>> for Node {
 }
 
 impl Node {
+    pub fn new_leaf(page_size: usize) -> Self {
+        let mut raw = vec![0; page_size];
+        raw[NODE_TYPE_OFFSET] = LEAF_NODE_TYPE;
+        Self::from(raw)
+    }
+
+    pub fn new_internal(page_size: usize) -> Self {
+        let mut raw = vec![0; page_size];
+        raw[NODE_TYPE_OFFSET] = INTERNAL_NODE_TYPE;
+        Self::from(raw)
+    }
+
     pub fn to_vec_mut(&mut self) -> &mut Vec<u8> {
         match self {
             Self::Leaf(n) => &mut n.raw,
@@ -79,6 +514,33 @@ impl Node {
         }
     }
 
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Leaf(n) => &n.raw,
+            Self::Internal(n) => &n.raw,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, Self::Leaf(_))
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.as_slice()[IS_ROOT_OFFSET] == 1
+    }
+
+    pub fn set_root(&mut self, is_root: bool) {
+        self.to_vec_mut()[IS_ROOT_OFFSET] = is_root as u8;
+    }
+
+    pub fn get_parent(&self) -> usize {
+        read_u32(self.as_slice(), PARENT_POINTER_OFFSET) as usize
+    }
+
+    pub fn set_parent(&mut self, page_num: usize) {
+        write_u32(self.to_vec_mut(), PARENT_POINTER_OFFSET, page_num as u32)
+    }
+
     fn get_cell(&mut self, cell_num: usize) -> &mut [u8] {
         match self {
             Self::Leaf(n) => n.get_cell(cell_num),
@@ -86,19 +548,23 @@ impl Node {
         }
     }
 
-    pub fn get_cell_key(&mut self, cell_num: usize) -> &mut [u8] {
-        let cell = self.get_cell(cell_num);
-        &mut cell[LEAF_NODE_KEY_OFFSET..LEAF_NODE_KEY_SIZE]
+    pub fn get_cell_key(&self, cell_num: usize) -> u32 {
+        match self {
+            Self::Leaf(n) => n.key(cell_num),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
     }
 
     pub fn set_cell_key(&mut self, cell_num: usize, key: u32) {
-        let cell_key = self.get_cell_key(cell_num);
-        cell_key[..].copy_from_slice(&key.to_be_bytes());
+        let cell = self.get_cell(cell_num);
+        write_u32(cell, LEAF_NODE_KEY_OFFSET, key);
     }
 
-    pub fn get_cell_value(&mut self, cell_num: usize) -> &mut [u8] {
-        let cell = self.get_cell(cell_num);
-        &mut cell[LEAF_NODE_VALUE_OFFSET..LEAF_NODE_VALUE_SIZE]
+    pub fn get_cell_value(&self, cell_num: usize) -> &[u8] {
+        match self {
+            Self::Leaf(n) => n.record(cell_num),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
     }
 
     pub fn get_cell_count(&self) -> usize {
@@ -108,38 +574,124 @@ impl Node {
         }
     }
 
-    pub fn insert(
-        &mut self,
-        key: u32,
-        value: &mut Row,
-        table: &mut Table,
-    ) -> Result<(), NodeError> {
-        let cursor = Cursor::end(table);
-        let cell_num = cursor.cell_num();
-        let mut page = table.pager.get_page_mut(cursor.page());
+    pub fn get_next_leaf(&self) -> usize {
+        match self {
+            Self::Leaf(n) => n.next_leaf(),
+            Self::Internal(_) => unreachable!("internal nodes do not have siblings"),
+        }
+    }
+
+    pub fn set_next_leaf(&mut self, page_num: usize) {
+        match self {
+            Self::Leaf(n) => n.set_next_leaf(page_num),
+            Self::Internal(_) => unreachable!("internal nodes do not have siblings"),
+        }
+    }
 
-        if page.cell_count() >= LEAF_NODE_MAX_CELLS {
-            // TODO: Need to implement splitting a leaf node
-            return Err(NodeError::NodeFullInsertError);
+    pub fn find_cell(&self, key: u32) -> usize {
+        match self {
+            Self::Leaf(n) => n.find(key),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
         }
+    }
 
-        if cell_num < page.cell_count() {
-            // Make room for new cell
-            for i in (cell_num..page.cell_count()).rev() {
-                let src = self.get_cell(i - 1).to_vec();
-                let dest = self.get_cell(i);
-                dest.copy_from_slice(&src[..]);
-            }
+    /// Bytes taken up by the cells of a leaf and their pointers.
+    pub fn get_used_space(&self) -> usize {
+        match self {
+            Self::Leaf(n) => n.used_space(),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
         }
+    }
 
-        page.increment_cell_count();
-        page.set_cell_key(key, cell_num);
+    pub fn has_room_for(&self, cell: &[u8]) -> bool {
+        match self {
+            Self::Leaf(n) => cell.len() + LEAF_NODE_CELL_POINTER_SIZE <= n.free_space(),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
 
-        let dest = page.get_cell_value(cell_num);
-        value
-            .serialize(dest)
-            .map_err(|e| NodeError::SerializationError(e))?;
+    /// Returns whether `cell` fits once the cell at `cell_num` is removed to make room.
+    pub fn has_room_to_replace(&self, cell_num: usize, cell: &[u8]) -> bool {
+        match self {
+            Self::Leaf(n) => cell.len() <= n.free_space() + n.cell(cell_num).len(),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
 
-        Ok(())
+    pub fn insert_cell(&mut self, cell_num: usize, cell: &[u8]) {
+        match self {
+            Self::Leaf(n) => n.insert_cell(cell_num, cell),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
+
+    pub fn remove_cell(&mut self, cell_num: usize) {
+        match self {
+            Self::Leaf(n) => n.remove_cell(cell_num),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
+
+    pub fn get_cells(&self) -> Vec<Vec<u8>> {
+        match self {
+            Self::Leaf(n) => n.cells(),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
+
+    pub fn set_cells(&mut self, cells: &[Vec<u8>]) {
+        match self {
+            Self::Leaf(n) => n.set_cells(cells),
+            Self::Internal(_) => unreachable!("internal nodes do not have cell"),
+        }
+    }
+
+    pub fn get_key_count(&self) -> usize {
+        match self {
+            Self::Internal(n) => n.key_count(),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have keys"),
+        }
+    }
+
+    pub fn get_child(&self, child_num: usize) -> usize {
+        match self {
+            Self::Internal(n) => n.child(child_num),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have children"),
+        }
+    }
+
+    pub fn get_key(&self, key_num: usize) -> u32 {
+        match self {
+            Self::Internal(n) => n.key(key_num),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have keys"),
+        }
+    }
+
+    pub fn find_child(&self, key: u32) -> usize {
+        match self {
+            Self::Internal(n) => n.find_child(key),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have children"),
+        }
+    }
+
+    pub fn get_children(&self) -> Vec<usize> {
+        match self {
+            Self::Internal(n) => n.children(),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have children"),
+        }
+    }
+
+    pub fn get_keys(&self) -> Vec<u32> {
+        match self {
+            Self::Internal(n) => n.keys(),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have keys"),
+        }
+    }
+
+    pub fn set_entries(&mut self, children: &[usize], keys: &[u32]) {
+        match self {
+            Self::Internal(n) => n.set_entries(children, keys),
+            Self::Leaf(_) => unreachable!("leaf nodes do not have children"),
+        }
     }
 }
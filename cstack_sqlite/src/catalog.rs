@@ -0,0 +1,200 @@
+//! The catalog records the definition of every table created in the database, like SQLite's
+//! `sqlite_master`. It is a tree of its own whose root page is kept in the file header, with a
+//! row per table holding its name, root page and `create table` statement.
+//!
+//! The users table is not recorded there. Its tree keeps the table's own root page for good,
+//! and every created table gets a tree of its own.
+use crate::{
+    btree::node::leaf_cell,
+    cursor::Cursor,
+    header::{self, HEADER_CATALOG_ROOT_OFFSET, HEADER_SCHEMA_COOKIE_OFFSET},
+    pager::PagerError,
+    row::{Row, Value},
+    schema::Schema,
+    statement::ExecuteError,
+    table::Table,
+};
+
+/// The table statements that name no table work on.
+pub const DEFAULT_TABLE: &str = "users";
+
+// Catalog Row Layout
+const CATALOG_ROOT_PAGE_COLUMN: usize = 1;
+const CATALOG_SQL_COLUMN: usize = 3;
+
+pub struct CatalogEntry {
+    root_page_num: usize,
+    schema: Schema,
+}
+
+pub struct Catalog {
+    // layout of the catalog's own rows
+    schema: Schema,
+    entries: Vec<CatalogEntry>,
+    // schema cookie in the header as of when the entries were read
+    schema_cookie: usize,
+    users: Schema,
+    users_root_page_num: usize,
+}
+
+impl Catalog {
+    /// Reads every table definition from the catalog tree. The users table is the one rooted
+    /// at `table`'s root page.
+    pub fn load(table: &mut Table) -> Result<Self, PagerError> {
+        let schema = Schema::parse(
+            "create table catalog (id integer, root_page integer, name text, sql text)",
+        )
+        .expect("the catalog definition is valid");
+
+        let mut catalog = Self {
+            schema,
+            entries: vec![],
+            schema_cookie: 0,
+            users: Schema::users(),
+            users_root_page_num: table.get_root_page_num(),
+        };
+        catalog.reload(table)?;
+
+        Ok(catalog)
+    }
+
+    fn reload(&mut self, table: &mut Table) -> Result<(), PagerError> {
+        self.entries.clear();
+        self.schema_cookie = header::read_field(&mut table.pager, HEADER_SCHEMA_COOKIE_OFFSET)?;
+
+        let root_page_num = header::read_field(&mut table.pager, HEADER_CATALOG_ROOT_OFFSET)?;
+        if root_page_num == 0 {
+            return Ok(());
+        }
+
+        let schema = &self.schema;
+        self.entries = table.with_tree(root_page_num, |table| {
+            let mut entries = vec![];
+            let mut cursor = Cursor::start(table)?;
+
+            while !cursor.end_of_table() {
+                let corrupt = |reason: String| PagerError::Corrupt {
+                    page: cursor.page(),
+                    reason,
+                };
+
+                let page = table.pager.get_page(cursor.page())?;
+                let row = Row::deserialize(schema, page.get_cell_value(cursor.cell_num()))
+                    .map_err(|_| {
+                        corrupt(format!(
+                            "cell {} is not a table definition",
+                            cursor.cell_num()
+                        ))
+                    })?;
+                let (Value::Integer(root_page_num), Value::Text(sql)) = (
+                    row.value(CATALOG_ROOT_PAGE_COLUMN),
+                    row.value(CATALOG_SQL_COLUMN),
+                ) else {
+                    return Err(corrupt(format!(
+                        "cell {} is not a table definition",
+                        cursor.cell_num()
+                    )));
+                };
+                let schema = Schema::parse(sql)
+                    .map_err(|_| corrupt(format!("table definition '{sql}' is invalid")))?;
+
+                entries.push(CatalogEntry {
+                    root_page_num: *root_page_num as usize,
+                    schema,
+                });
+                cursor.advance(table)?;
+            }
+
+            Ok::<_, PagerError>(entries)
+        })?;
+
+        Ok(())
+    }
+
+    /// Reloads the catalog when the schema changed since it was read, whether another
+    /// connection created a table or a `create table` was rolled back. Returns whether it did.
+    pub fn refresh(&mut self, table: &mut Table) -> Result<bool, PagerError> {
+        if header::read_field(&mut table.pager, HEADER_SCHEMA_COOKIE_OFFSET)? == self.schema_cookie
+        {
+            return Ok(false);
+        }
+
+        self.reload(table)?;
+        Ok(true)
+    }
+
+    /// Every table with its root page, starting with the users table.
+    fn tables(&self) -> impl Iterator<Item = (usize, &Schema)> {
+        std::iter::once((self.users_root_page_num, &self.users)).chain(
+            self.entries
+                .iter()
+                .map(|entry| (entry.root_page_num, &entry.schema)),
+        )
+    }
+
+    /// Returns the root page and schema of the table called `name`.
+    pub fn table(&self, name: &str) -> Option<(usize, &Schema)> {
+        self.tables().find(|(_, schema)| schema.name() == name)
+    }
+
+    /// Records `schema` in the catalog and gives the table a tree of its own.
+    pub fn create_table(&mut self, table: &mut Table, schema: Schema) -> Result<(), ExecuteError> {
+        if self.table(schema.name()).is_some() {
+            return Err(ExecuteError::SchemaError(format!(
+                "table {} already exists",
+                schema.name()
+            )));
+        }
+
+        let id = self.entries.len() + 1;
+        let mut row = Row::new(vec![
+            Value::Integer(id as i64),
+            // the largest root page there can be, until the table has one
+            Value::Integer(u32::MAX.into()),
+            Value::Text(schema.name().to_string()),
+            Value::Text(schema.sql().to_string()),
+        ]);
+        // checked before any page is allocated, so a definition too long to store leaves
+        // nothing behind
+        leaf_cell(id as u32, &row, &self.schema, table.pager.page_size())
+            .map_err(|_| ExecuteError::SchemaError("table definition is too long".to_string()))?;
+
+        let catalog_root_page_num = self.root_page_num(table)?;
+        let root_page_num = table.create_tree()?;
+
+        row.set_value(
+            CATALOG_ROOT_PAGE_COLUMN,
+            Value::Integer(root_page_num as i64),
+        );
+
+        table.with_tree(catalog_root_page_num, |table| {
+            let cursor = table.find(id as u32)?;
+            table.insert(&cursor, &row, &self.schema)
+        })?;
+
+        // other connections reload their catalog once they see the cookie change
+        let schema_cookie = (self.schema_cookie as u32).wrapping_add(1) as usize;
+        header::write_field(&mut table.pager, HEADER_SCHEMA_COOKIE_OFFSET, schema_cookie)?;
+        self.schema_cookie = schema_cookie;
+        self.entries.push(CatalogEntry {
+            root_page_num,
+            schema,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the root page of the catalog tree, allocating it when the first table is
+    /// created.
+    fn root_page_num(&self, table: &mut Table) -> Result<usize, PagerError> {
+        let root_page_num = header::read_field(&mut table.pager, HEADER_CATALOG_ROOT_OFFSET)?;
+        if root_page_num != 0 {
+            return Ok(root_page_num);
+        }
+
+        let root_page_num = table.create_tree()?;
+        header::write_field(&mut table.pager, HEADER_CATALOG_ROOT_OFFSET, root_page_num)?;
+
+        Ok(root_page_num)
+    }
+}
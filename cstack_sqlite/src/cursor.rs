@@ -1,4 +1,6 @@
-use crate::table::Table;
+use std::ops::Bound;
+
+use crate::{pager::PagerError, table::Table};
 
 pub struct Cursor {
     pub(self) page: usize,
@@ -7,26 +9,72 @@ pub struct Cursor {
 }
 
 impl Cursor {
-    pub fn start(table: &mut Table) -> Self {
-        let root_page_num = table.get_root_page_num();
-        let root_page = table.pager.get_page_mut(root_page_num);
-        let end_of_table = root_page.cell_count() == 0;
-
+    pub(crate) fn new(page: usize, cell: usize, end_of_table: bool) -> Self {
         Self {
-            page: root_page_num,
-            cell: 0,
+            page,
+            cell,
             end_of_table,
         }
     }
 
-    pub fn end(table: &mut Table) -> Self {
-        let root_page_num = table.get_root_page_num();
-        let root_page = table.pager.get_page_mut(root_page_num);
+    pub fn start(table: &mut Table) -> Result<Self, PagerError> {
+        let page_num = Self::descend(table, |_| 0)?;
+        let mut cursor = Self::new(page_num, 0, false);
+        cursor.skip_exhausted_leaves(table)?;
+        Ok(cursor)
+    }
 
-        Self {
-            page: root_page_num,
-            cell: root_page.cell_count(),
+    /// Positions a cursor on the first cell that is inside `start`, so a range scan only
+    /// reads the leaves it needs.
+    pub fn seek(table: &mut Table, start: Bound<u32>) -> Result<Self, PagerError> {
+        let (Bound::Included(key) | Bound::Excluded(key)) = start else {
+            return Self::start(table);
+        };
+
+        let mut cursor = table.find(key)?;
+        cursor.skip_exhausted_leaves(table)?;
+
+        if let Bound::Excluded(key) = start {
+            if !cursor.end_of_table()
+                && table
+                    .pager
+                    .get_page(cursor.page)?
+                    .node()
+                    .get_cell_key(cursor.cell)
+                    == key
+            {
+                cursor.advance(table)?;
+            }
+        }
+
+        Ok(cursor)
+    }
+
+    pub fn end(table: &mut Table) -> Result<Self, PagerError> {
+        let page_num = Self::descend(table, |key_count| key_count)?;
+        let page = table.pager.get_page(page_num)?;
+
+        Ok(Self {
+            page: page_num,
+            cell: page.cell_count(),
             end_of_table: true,
+        })
+    }
+
+    /// Walks down from the root to a leaf, following the child `pick_child` chooses
+    /// given the number of keys in each internal node.
+    fn descend(
+        table: &mut Table,
+        pick_child: impl Fn(usize) -> usize,
+    ) -> Result<usize, PagerError> {
+        let mut page_num = table.get_root_page_num();
+
+        loop {
+            let node = table.pager.get_page(page_num)?.node();
+            if node.is_leaf() {
+                return Ok(page_num);
+            }
+            page_num = node.get_child(pick_child(node.get_key_count()));
         }
     }
 
@@ -42,9 +90,32 @@ impl Cursor {
         self.page
     }
 
-    pub fn advance(&mut self, table: &mut Table) {
-        let page = table.pager.get_page_mut(self.page);
+    /// Moves to the next cell, following the next-leaf pointer once the current leaf runs out
+    /// so a scan walks every leaf from left to right.
+    pub fn advance(&mut self, table: &mut Table) -> Result<(), PagerError> {
         self.cell += 1;
-        self.end_of_table = self.cell >= page.cell_count();
+        self.skip_exhausted_leaves(table)
+    }
+
+    fn skip_exhausted_leaves(&mut self, table: &mut Table) -> Result<(), PagerError> {
+        loop {
+            let node = table.pager.get_page(self.page)?.node();
+
+            if self.cell < node.get_cell_count() {
+                self.end_of_table = false;
+                return Ok(());
+            }
+
+            match node.get_next_leaf() {
+                0 => {
+                    self.end_of_table = true;
+                    return Ok(());
+                }
+                next_leaf => {
+                    self.page = next_leaf;
+                    self.cell = 0;
+                }
+            }
+        }
     }
 }
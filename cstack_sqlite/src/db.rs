@@ -1,15 +1,53 @@
+use std::{
+    fmt,
+    io::{self, stdout, Write},
+    rc::Rc,
+};
+
 use crate::{
-    pager::Pager,
+    catalog::Catalog,
+    meta::handlers::MetaHandleError,
+    pager::{Pager, PagerConfig, PagerError},
     statement::{ExecuteError, Statement, StatementError},
     table::Table,
+    vfs::{LockMode, MemoryVfs},
 };
 
+/// Opening this filename gives a database that lives in memory and is never written to disk.
+pub const MEMORY_DB_FILENAME: &str = ":memory:";
+
+// every statement in a transaction runs inside this savepoint, so one that fails is undone
+// without the rest of the transaction. Savepoints named in statements are never empty, so
+// it cannot be mistaken for one of them
+const STATEMENT_SAVEPOINT: &str = "";
+
 pub struct Database {
     table: Table,
+    catalog: Catalog,
+    // set between `begin` and `commit`/`rollback`, otherwise every statement commits itself
+    in_transaction: bool,
 }
 
+#[derive(Debug)]
 pub enum DatabaseError {
-    CloseError,
+    OpenError(PagerError),
+    CloseError(PagerError),
+    OutputError(io::Error),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OpenError(e) | Self::CloseError(e) => write!(f, "{e}"),
+            Self::OutputError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(e: io::Error) -> Self {
+        DatabaseError::OutputError(e)
+    }
 }
 
 pub enum HandleDBQueryStatusCode {
@@ -17,14 +55,43 @@ pub enum HandleDBQueryStatusCode {
     Continue,
 }
 
-impl<'a> Database {
-    pub fn try_new(filename: &str) -> std::io::Result<Self> {
-        let pager = Pager::try_new(filename.into())?;
-        let table = Table::new(pager);
-        Ok(Self { table })
+impl Database {
+    pub fn try_new(filename: &str) -> Result<Self, DatabaseError> {
+        Self::try_new_with_config(filename, PagerConfig::default())
+    }
+
+    pub fn try_new_with_config(
+        filename: &str,
+        mut config: PagerConfig,
+    ) -> Result<Self, DatabaseError> {
+        if filename == MEMORY_DB_FILENAME {
+            config.vfs = Rc::new(MemoryVfs::default());
+        }
+
+        let pager = Pager::try_new(filename.into(), config).map_err(DatabaseError::OpenError)?;
+        let mut table = Table::new(pager).map_err(DatabaseError::OpenError)?;
+        let catalog = Catalog::load(&mut table).map_err(DatabaseError::OpenError)?;
+        // a new file's first pages are committed right away so a rollback cannot undo them
+        table.flush_pages().map_err(DatabaseError::OpenError)?;
+        table.pager.unlock().map_err(DatabaseError::OpenError)?;
+
+        Ok(Self {
+            table,
+            catalog,
+            in_transaction: false,
+        })
     }
 
     pub fn handle_query(&mut self, query: &str) -> Result<HandleDBQueryStatusCode, DatabaseError> {
+        self.handle_query_to(query, &mut stdout())
+    }
+
+    /// Like `handle_query`, writing the rows and messages to `out` instead of stdout.
+    pub fn handle_query_to(
+        &mut self,
+        query: &str,
+        out: &mut dyn Write,
+    ) -> Result<HandleDBQueryStatusCode, DatabaseError> {
         let query = query.trim();
 
         if query.is_empty() {
@@ -36,39 +103,248 @@ impl<'a> Database {
                 self.close()?;
                 return Ok(HandleDBQueryStatusCode::Exit);
             }
-            value if value.starts_with(".") => {
-                if let Err(_) = crate::meta::handlers::handle(value) {
-                    println!("Unrecognised command '{}'", value)
+            value if value.starts_with(".") => match self.handle_meta_command(value, out) {
+                Ok(_) => {}
+                Err(MetaHandleError::UnrecognisedCommand) => {
+                    writeln!(out, "Unrecognised command '{}'", value)?
+                }
+                Err(MetaHandleError::PagerError(e)) => writeln!(out, "Error: {e}")?,
+                Err(MetaHandleError::OutputError(e)) => return Err(e.into()),
+            },
+            value => {
+                if let Err(e) = self.refresh_catalog() {
+                    writeln!(out, "Error: {e}")?;
+                    return Ok(HandleDBQueryStatusCode::Continue);
+                }
+
+                let executed = Statement::new(value, &self.catalog)
+                    .map_err(ExecuteError::from)
+                    .and_then(|statement| self.execute(value, statement, out));
+                match executed {
+                    Ok(_) => writeln!(out, "Executed.")?,
+                    Err(ExecuteError::DuplicateKey) => writeln!(out, "Error: Duplicate key")?,
+                    Err(ExecuteError::SerializationFail(s)) => writeln!(out, "{}", s)?,
+                    Err(ExecuteError::PagerError(e)) => writeln!(out, "Error: {e}")?,
+                    Err(ExecuteError::TransactionError(s)) => writeln!(out, "Error: {s}")?,
+                    Err(ExecuteError::SchemaError(s)) => writeln!(out, "Error: {s}")?,
+                    Err(ExecuteError::OutputError(e)) => return Err(e.into()),
+                    Err(ExecuteError::StatementError(e)) => match e {
+                        StatementError::SynthaxError(t) => writeln!(out, "Syntax Error: {}", t)?,
+                        StatementError::UnrecognisedStatement => {
+                            writeln!(out, "Unrecognized keyword at start of '{}'", value)?
+                        }
+                        StatementError::ValidationError(s) => {
+                            writeln!(out, "Validation Error: {}", s)?
+                        }
+                    },
                 }
             }
-            value => match Statement::new(value) {
-                Ok(mut statement) => match statement.execute(&mut self.table) {
-                    Ok(_) => println!("Executed."),
-                    Err(ExecuteError::TableFull) => {
-                        println!("Error: Table Full")
+        }
+
+        Ok(HandleDBQueryStatusCode::Continue)
+    }
+
+    /// Runs `statement`, parsed from `query`, handling transaction control here since it spans
+    /// statements. Outside a transaction the statement is committed when it succeeds and
+    /// rolled back when it fails, so it never leaves partial changes behind.
+    fn execute(
+        &mut self,
+        query: &str,
+        mut statement: Statement,
+        out: &mut dyn Write,
+    ) -> Result<(), ExecuteError> {
+        match statement {
+            Statement::Begin => {
+                if self.in_transaction {
+                    return Err(ExecuteError::TransactionError(
+                        "cannot start a transaction within a transaction".to_string(),
+                    ));
+                }
+                // the transaction reads one state of the database until it ends, and raises
+                // the lock once it writes
+                self.table.pager.lock(LockMode::Shared)?;
+                self.in_transaction = true;
+                Ok(())
+            }
+            Statement::Commit => {
+                self.require_transaction()?;
+                match self.table.flush_pages() {
+                    // nothing is written while readers keep the commit waiting, so the
+                    // transaction stays open to be committed again
+                    Err(e @ PagerError::Busy) => Err(e.into()),
+                    flushed => {
+                        self.end_transaction()?;
+                        self.finish(flushed.is_ok())?;
+                        Ok(flushed?)
                     }
-                    Err(ExecuteError::SerializationFail(s)) => println!("{}", s),
-                },
-                Err(e) => match e {
-                    StatementError::SynthaxError(t) => {
-                        println!("Syntax Error: {}", t)
+                }
+            }
+            Statement::Rollback => {
+                self.end_transaction()?;
+                Ok(self.finish(false)?)
+            }
+            Statement::Savepoint { name } => {
+                self.require_transaction()?;
+                self.table.pager.savepoint(&name);
+                Ok(())
+            }
+            Statement::Release { name } => {
+                self.require_transaction()?;
+                if !self.table.pager.release(&name) {
+                    return Err(ExecuteError::TransactionError(format!(
+                        "no such savepoint: {name}"
+                    )));
+                }
+                Ok(())
+            }
+            Statement::RollbackTo { name } => {
+                self.require_transaction()?;
+                if !self.table.pager.rollback_to(&name)? {
+                    return Err(ExecuteError::TransactionError(format!(
+                        "no such savepoint: {name}"
+                    )));
+                }
+                Ok(())
+            }
+            _ => {
+                // a writer shares the file with readers until it commits
+                let mode = match statement {
+                    Statement::Select { .. } => LockMode::Shared,
+                    _ => LockMode::Reserved,
+                };
+                self.table.pager.lock(mode)?;
+
+                if !self.in_transaction {
+                    // the statement was parsed against the catalog as it was before locking, so
+                    // it is parsed again when another connection has changed the schema since
+                    let reparsed = match self.catalog.refresh(&mut self.table) {
+                        Ok(false) => Ok(None),
+                        Ok(true) => Statement::new(query, &self.catalog)
+                            .map(Some)
+                            .map_err(ExecuteError::from),
+                        Err(e) => Err(e.into()),
+                    };
+                    match reparsed {
+                        Ok(Some(reparsed)) => statement = reparsed,
+                        Ok(None) => {}
+                        Err(e) => {
+                            self.table.pager.unlock()?;
+                            return Err(e);
+                        }
                     }
-                    StatementError::UnrecognisedStatement => {
-                        println!("Unrecognized keyword at start of '{}'", value)
+                }
+
+                if !self.in_transaction {
+                    let result = self.execute_on_table(statement, out);
+                    self.finish(result.is_ok())?;
+                    return result;
+                }
+
+                self.table.pager.savepoint(STATEMENT_SAVEPOINT);
+                let result = self.execute_on_table(statement, out);
+                match result {
+                    Ok(_) => {
+                        self.table.pager.release(STATEMENT_SAVEPOINT);
                     }
-                    StatementError::ValidationError(s) => {
-                        println!("Validation Error: {}", s)
+                    Err(_) => {
+                        self.table.pager.rollback_to(STATEMENT_SAVEPOINT)?;
+                        self.table.pager.release(STATEMENT_SAVEPOINT);
                     }
-                },
-            },
+                }
+                result
+            }
         }
+    }
 
-        Ok(HandleDBQueryStatusCode::Continue)
+    /// Commits the changes made under the lock held, or rolls them back when `commit` is
+    /// false, then releases the lock whatever happened. A commit that fails partway is
+    /// rolled back too, so none of it stays behind in the cache.
+    fn finish(&mut self, commit: bool) -> Result<(), PagerError> {
+        let finished = if commit {
+            self.table.flush_pages().or_else(|e| {
+                self.table.pager.rollback()?;
+                Err(e)
+            })
+        } else {
+            self.table.pager.rollback()
+        };
+
+        let unlocked = self.table.pager.unlock();
+        finished.and(unlocked)
     }
 
-    pub fn close(&mut self) -> Result<(), DatabaseError> {
+    /// Runs a statement on rows against the tree of the table it names, or creates a table.
+    fn execute_on_table(
+        &mut self,
+        statement: Statement,
+        out: &mut dyn Write,
+    ) -> Result<(), ExecuteError> {
+        if let Statement::CreateTable { schema } = statement {
+            return self.catalog.create_table(&mut self.table, schema);
+        }
+
+        let name = statement
+            .table()
+            .expect("only statements on rows are left to run");
+        let (root_page_num, schema) = self
+            .catalog
+            .table(name)
+            .ok_or_else(|| ExecuteError::SchemaError(format!("no such table: {name}")))?;
+
         self.table
-            .flush_pages()
-            .map_err(|_| DatabaseError::CloseError)
+            .with_tree(root_page_num, |table| statement.execute(table, schema, out))
+    }
+
+    /// Brings the catalog up to date before a statement is parsed against it, taking a shared
+    /// lock while the header is read unless a transaction already holds one.
+    fn refresh_catalog(&mut self) -> Result<(), PagerError> {
+        if self.in_transaction {
+            return self.catalog.refresh(&mut self.table).map(|_| ());
+        }
+
+        self.table.pager.lock(LockMode::Shared)?;
+        let refreshed = self.catalog.refresh(&mut self.table);
+        self.table.pager.unlock()?;
+        refreshed.map(|_| ())
+    }
+
+    /// Runs a meta command under a shared lock, which the ones that write to the file raise.
+    fn handle_meta_command(
+        &mut self,
+        command: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), MetaHandleError> {
+        if self.in_transaction {
+            return crate::meta::handlers::handle(command, &mut self.table, out);
+        }
+
+        self.table.pager.lock(LockMode::Shared)?;
+        let result = crate::meta::handlers::handle(command, &mut self.table, out);
+        self.table.pager.unlock()?;
+        result
+    }
+
+    fn require_transaction(&self) -> Result<(), ExecuteError> {
+        if !self.in_transaction {
+            return Err(ExecuteError::TransactionError(
+                "no transaction is active".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn end_transaction(&mut self) -> Result<(), ExecuteError> {
+        self.require_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    /// Closes the database. A transaction that is still open is rolled back.
+    pub fn close(&mut self) -> Result<(), DatabaseError> {
+        let commit = !self.in_transaction;
+        self.in_transaction = false;
+
+        self.finish(commit).map_err(DatabaseError::CloseError)
     }
 }
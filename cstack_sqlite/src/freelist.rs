@@ -0,0 +1,80 @@
+//! Pages that are no longer part of the tree are kept on a freelist so they can be handed out
+//! again before the file grows. Like SQLite, the list is a chain of trunk pages, each holding
+//! the page number of the next trunk and an array of free leaf page numbers. The first trunk
+//! and the number of free pages are kept in the file header.
+use crate::{
+    header::{self, HEADER_FREELIST_COUNT_OFFSET, HEADER_FREELIST_TRUNK_OFFSET},
+    page::{read_u32, write_u32},
+    pager::{usable_size, Pager, PagerError},
+};
+
+// Trunk Page Layout
+pub const TRUNK_NEXT_TRUNK_SIZE: usize = std::mem::size_of::<u32>();
+pub const TRUNK_NEXT_TRUNK_OFFSET: usize = 0;
+pub const TRUNK_NUM_LEAVES_SIZE: usize = std::mem::size_of::<u32>();
+pub const TRUNK_NUM_LEAVES_OFFSET: usize = TRUNK_NEXT_TRUNK_OFFSET + TRUNK_NEXT_TRUNK_SIZE;
+pub const TRUNK_HEADER_SIZE: usize = TRUNK_NEXT_TRUNK_SIZE + TRUNK_NUM_LEAVES_SIZE;
+pub const TRUNK_LEAF_SIZE: usize = std::mem::size_of::<u32>();
+
+pub const fn trunk_max_leaves(page_size: usize) -> usize {
+    (usable_size(page_size) - TRUNK_HEADER_SIZE) / TRUNK_LEAF_SIZE
+}
+
+pub fn count(pager: &mut Pager) -> Result<usize, PagerError> {
+    header::read_field(pager, HEADER_FREELIST_COUNT_OFFSET)
+}
+
+/// Adds `page_num` to the freelist. It is recorded as a leaf of the first trunk when that
+/// trunk has room, otherwise the page itself becomes the new first trunk.
+pub fn push(pager: &mut Pager, page_num: usize) -> Result<(), PagerError> {
+    let trunk_page_num = header::read_field(pager, HEADER_FREELIST_TRUNK_OFFSET)?;
+
+    if trunk_page_num != 0 {
+        let trunk = pager.get_page_mut(trunk_page_num)?.to_vec_mut();
+        let num_leaves = read_u32(trunk, TRUNK_NUM_LEAVES_OFFSET) as usize;
+
+        if num_leaves < trunk_max_leaves(trunk.len()) {
+            let offset = TRUNK_HEADER_SIZE + num_leaves * TRUNK_LEAF_SIZE;
+            write_u32(trunk, offset, page_num as u32);
+            write_u32(trunk, TRUNK_NUM_LEAVES_OFFSET, (num_leaves + 1) as u32);
+            let count = count(pager)?;
+            return header::write_field(pager, HEADER_FREELIST_COUNT_OFFSET, count + 1);
+        }
+    }
+
+    let trunk = pager.get_page_mut(page_num)?.to_vec_mut();
+    write_u32(trunk, TRUNK_NEXT_TRUNK_OFFSET, trunk_page_num as u32);
+    write_u32(trunk, TRUNK_NUM_LEAVES_OFFSET, 0);
+    header::write_field(pager, HEADER_FREELIST_TRUNK_OFFSET, page_num)?;
+    let count = count(pager)?;
+    header::write_field(pager, HEADER_FREELIST_COUNT_OFFSET, count + 1)
+}
+
+/// Takes a page off the freelist, preferring the leaves of the first trunk and falling back
+/// to the trunk page itself once it has none left.
+pub fn pop(pager: &mut Pager) -> Result<Option<usize>, PagerError> {
+    let trunk_page_num = header::read_field(pager, HEADER_FREELIST_TRUNK_OFFSET)?;
+
+    if trunk_page_num == 0 {
+        return Ok(None);
+    }
+
+    let trunk = pager.get_page_mut(trunk_page_num)?.to_vec_mut();
+    let num_leaves = read_u32(trunk, TRUNK_NUM_LEAVES_OFFSET) as usize;
+
+    let page_num = if num_leaves > 0 {
+        let offset = TRUNK_HEADER_SIZE + (num_leaves - 1) * TRUNK_LEAF_SIZE;
+        let leaf_page_num = read_u32(trunk, offset) as usize;
+        write_u32(trunk, TRUNK_NUM_LEAVES_OFFSET, (num_leaves - 1) as u32);
+        leaf_page_num
+    } else {
+        let next_trunk = read_u32(trunk, TRUNK_NEXT_TRUNK_OFFSET) as usize;
+        header::write_field(pager, HEADER_FREELIST_TRUNK_OFFSET, next_trunk)?;
+        trunk_page_num
+    };
+
+    let count = count(pager)?;
+    header::write_field(pager, HEADER_FREELIST_COUNT_OFFSET, count - 1)?;
+
+    Ok(Some(page_num))
+}
@@ -0,0 +1,115 @@
+//! Page 0 starts with the file header, which marks the file as a database and records what
+//! is needed to read the rest of it. It is checked whenever a database is opened, before
+//! anything else in the file is trusted.
+use crate::{
+    page::{read_u32, write_u32},
+    pager::{is_valid_page_size, Pager, PagerError},
+};
+
+pub const HEADER_PAGE_NUM: usize = 0;
+pub const HEADER_MAGIC: &[u8] = b"cstack sqlite\0\0\0";
+// bumped whenever the file layout changes in a way older versions cannot read
+pub const FORMAT_VERSION: u32 = 3;
+
+// File Header Layout
+pub const HEADER_MAGIC_SIZE: usize = HEADER_MAGIC.len();
+pub const HEADER_MAGIC_OFFSET: usize = 0;
+pub const HEADER_VERSION_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_VERSION_OFFSET: usize = HEADER_MAGIC_OFFSET + HEADER_MAGIC_SIZE;
+pub const HEADER_PAGE_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_PAGE_SIZE_OFFSET: usize = HEADER_VERSION_OFFSET + HEADER_VERSION_SIZE;
+// database size in pages as of the last commit
+pub const HEADER_PAGE_COUNT_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_PAGE_COUNT_OFFSET: usize = HEADER_PAGE_SIZE_OFFSET + HEADER_PAGE_SIZE_SIZE;
+pub const HEADER_FREELIST_TRUNK_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_FREELIST_TRUNK_OFFSET: usize = HEADER_PAGE_COUNT_OFFSET + HEADER_PAGE_COUNT_SIZE;
+pub const HEADER_FREELIST_COUNT_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_FREELIST_COUNT_OFFSET: usize =
+    HEADER_FREELIST_TRUNK_OFFSET + HEADER_FREELIST_TRUNK_SIZE;
+// changed whenever the schema changes, so cached schema information can be checked
+pub const HEADER_SCHEMA_COOKIE_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_SCHEMA_COOKIE_OFFSET: usize =
+    HEADER_FREELIST_COUNT_OFFSET + HEADER_FREELIST_COUNT_SIZE;
+// bumped by every commit, so other connections can tell their cached pages are stale
+pub const HEADER_CHANGE_COUNTER_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_CHANGE_COUNTER_OFFSET: usize =
+    HEADER_SCHEMA_COOKIE_OFFSET + HEADER_SCHEMA_COOKIE_SIZE;
+// root page of the catalog tree, 0 until the first table is created
+pub const HEADER_CATALOG_ROOT_SIZE: usize = std::mem::size_of::<u32>();
+pub const HEADER_CATALOG_ROOT_OFFSET: usize =
+    HEADER_CHANGE_COUNTER_OFFSET + HEADER_CHANGE_COUNTER_SIZE;
+pub const HEADER_SIZE: usize = HEADER_CATALOG_ROOT_OFFSET + HEADER_CATALOG_ROOT_SIZE;
+
+/// Writes the header of a new, empty database into page 0, whose size is the database's
+/// page size.
+pub fn init(raw: &mut [u8]) {
+    let page_size = raw.len();
+    raw[..HEADER_SIZE].fill(0);
+    raw[..HEADER_MAGIC_SIZE].copy_from_slice(HEADER_MAGIC);
+    write_u32(raw, HEADER_VERSION_OFFSET, FORMAT_VERSION);
+    write_u32(raw, HEADER_PAGE_SIZE_OFFSET, page_size as u32);
+}
+
+/// Returns the page size recorded in a header read from the start of a file, after checking
+/// that it is the header of a database this version can read.
+pub fn read_page_size(raw: &[u8]) -> Result<usize, PagerError> {
+    if raw.len() < HEADER_SIZE || &raw[..HEADER_MAGIC_SIZE] != HEADER_MAGIC {
+        return Err(PagerError::NotADatabase);
+    }
+
+    let version = read_u32(raw, HEADER_VERSION_OFFSET);
+    if version > FORMAT_VERSION {
+        return Err(PagerError::UnsupportedFormat(format!(
+            "format version {version} is newer than version {FORMAT_VERSION}"
+        )));
+    }
+    if version < FORMAT_VERSION {
+        return Err(PagerError::UnsupportedFormat(format!(
+            "format version {version} is older than version {FORMAT_VERSION}"
+        )));
+    }
+
+    let page_size = read_u32(raw, HEADER_PAGE_SIZE_OFFSET) as usize;
+    if !is_valid_page_size(page_size) {
+        return Err(PagerError::UnsupportedFormat(format!(
+            "page size {page_size}"
+        )));
+    }
+
+    Ok(page_size)
+}
+
+/// Checks the header in page 0, as read into `raw`, against the file it was read from, which
+/// holds `page_count` pages.
+pub fn validate(raw: &[u8], page_count: usize) -> Result<(), PagerError> {
+    let page_size = read_page_size(raw)?;
+    if page_size != raw.len() {
+        return Err(PagerError::Corrupt {
+            page: 0,
+            reason: format!("header page size {page_size} does not match {}", raw.len()),
+        });
+    }
+
+    let header_page_count = read_u32(raw, HEADER_PAGE_COUNT_OFFSET) as usize;
+    if header_page_count > page_count {
+        return Err(PagerError::Corrupt {
+            page: page_count,
+            reason: format!("file is truncated to {page_count} of {header_page_count} pages"),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn read_field(pager: &mut Pager, offset: usize) -> Result<usize, PagerError> {
+    Ok(read_u32(pager.get_page(HEADER_PAGE_NUM)?.as_slice(), offset) as usize)
+}
+
+pub fn write_field(pager: &mut Pager, offset: usize, value: usize) -> Result<(), PagerError> {
+    write_u32(
+        pager.get_page_mut(HEADER_PAGE_NUM)?.to_vec_mut(),
+        offset,
+        value as u32,
+    );
+    Ok(())
+}
@@ -0,0 +1,298 @@
+//! Rollback journal. Before a page in the database file is overwritten, its original image is
+//! appended to `<db>-journal` and synced, so changes that never finished committing can be
+//! undone by copying the originals back. The journal is deleted once a commit is complete;
+//! one that is still there when the database is opened is hot and gets rolled back.
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    checksum::crc32,
+    page::{read_u32, write_u32, Page},
+    pager::{is_valid_page_size, PagerError, Synchronous},
+    vfs::{Vfs, VfsFile},
+};
+
+pub const JOURNAL_MAGIC: &[u8] = b"csqjrnl\0";
+
+// Journal Header Layout
+pub const JOURNAL_MAGIC_SIZE: usize = JOURNAL_MAGIC.len();
+pub const JOURNAL_MAGIC_OFFSET: usize = 0;
+pub const JOURNAL_PAGE_COUNT_SIZE: usize = std::mem::size_of::<u32>();
+pub const JOURNAL_PAGE_COUNT_OFFSET: usize = JOURNAL_MAGIC_OFFSET + JOURNAL_MAGIC_SIZE;
+pub const JOURNAL_PAGE_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+pub const JOURNAL_PAGE_SIZE_OFFSET: usize = JOURNAL_PAGE_COUNT_OFFSET + JOURNAL_PAGE_COUNT_SIZE;
+pub const JOURNAL_HEADER_CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+pub const JOURNAL_HEADER_CHECKSUM_OFFSET: usize = JOURNAL_PAGE_SIZE_OFFSET + JOURNAL_PAGE_SIZE_SIZE;
+pub const JOURNAL_HEADER_SIZE: usize = JOURNAL_MAGIC_SIZE
+    + JOURNAL_PAGE_COUNT_SIZE
+    + JOURNAL_PAGE_SIZE_SIZE
+    + JOURNAL_HEADER_CHECKSUM_SIZE;
+
+// Journal Record Layout
+// each record is a page number, the page's original image and a checksum over both, where
+// the image is as long as the page size in the header
+pub const RECORD_PAGE_NUM_SIZE: usize = std::mem::size_of::<u32>();
+pub const RECORD_PAGE_NUM_OFFSET: usize = 0;
+pub const RECORD_IMAGE_OFFSET: usize = RECORD_PAGE_NUM_OFFSET + RECORD_PAGE_NUM_SIZE;
+pub const RECORD_CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
+pub const fn record_size(page_size: usize) -> usize {
+    RECORD_IMAGE_OFFSET + page_size + RECORD_CHECKSUM_SIZE
+}
+
+pub fn journal_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push("-journal");
+    PathBuf::from(path)
+}
+
+/// Returns whether there is a journal at `path` that is hot: one that no connection holds a
+/// reserved lock on `db_file` for. Writers delete theirs before unlocking, so it can only be
+/// left behind by one that stopped partway through a commit.
+fn is_hot(vfs: &dyn Vfs, path: &Path, db_file: &dyn VfsFile) -> Result<bool, PagerError> {
+    if !vfs.exists(path).map_err(PagerError::JournalFailed)? {
+        return Ok(false);
+    }
+
+    Ok(!db_file.is_reserved().map_err(PagerError::LockFailed)?)
+}
+
+/// Copies the original images in `journal` back into the database file, up to the first
+/// incomplete record, which can only be one whose page was never overwritten, and cuts the
+/// file back to its original length. A journal without a complete header was never synced,
+/// so the database file was not touched yet. Returns whether anything was restored.
+fn roll_back(journal: &dyn VfsFile, db_file: &dyn VfsFile) -> Result<bool, PagerError> {
+    let mut header = [0; JOURNAL_HEADER_SIZE];
+    let header_valid = journal.read_at(&mut header, 0).is_ok()
+        && &header[..JOURNAL_MAGIC_SIZE] == JOURNAL_MAGIC
+        && read_u32(&header, JOURNAL_HEADER_CHECKSUM_OFFSET)
+            == crc32(&header[..JOURNAL_HEADER_CHECKSUM_OFFSET])
+        && is_valid_page_size(read_u32(&header, JOURNAL_PAGE_SIZE_OFFSET) as usize);
+
+    if !header_valid {
+        return Ok(false);
+    }
+
+    let original_page_count = read_u32(&header, JOURNAL_PAGE_COUNT_OFFSET) as usize;
+    let page_size = read_u32(&header, JOURNAL_PAGE_SIZE_OFFSET) as usize;
+    let checksum_offset = RECORD_IMAGE_OFFSET + page_size;
+    let mut record = vec![0; record_size(page_size)];
+    let mut offset = JOURNAL_HEADER_SIZE;
+
+    while journal.read_at(&mut record, offset as u64).is_ok() {
+        if read_u32(&record, checksum_offset) != crc32(&record[..checksum_offset]) {
+            break;
+        }
+
+        let page_num = read_u32(&record, RECORD_PAGE_NUM_OFFSET) as usize;
+        db_file
+            .write_at(
+                &record[RECORD_IMAGE_OFFSET..checksum_offset],
+                (page_num * page_size) as u64,
+            )
+            .map_err(PagerError::JournalFailed)?;
+        offset += record.len();
+    }
+
+    db_file
+        .truncate((original_page_count * page_size) as u64)
+        .map_err(PagerError::JournalFailed)?;
+    db_file.sync().map_err(PagerError::JournalFailed)?;
+
+    Ok(true)
+}
+
+pub struct Journal {
+    vfs: Rc<dyn Vfs>,
+    path: PathBuf,
+    file: Option<Box<dyn VfsFile>>,
+    page_size: usize,
+    synchronous: Synchronous,
+    len: usize,
+    synced: bool,
+    // number of pages the database file had when the journal was started
+    original_page_count: usize,
+    journaled: HashSet<usize>,
+}
+
+impl Journal {
+    pub fn new(
+        db_path: &Path,
+        vfs: Rc<dyn Vfs>,
+        page_size: usize,
+        synchronous: Synchronous,
+    ) -> Self {
+        Self {
+            vfs,
+            path: journal_path(db_path),
+            file: None,
+            page_size,
+            synchronous,
+            len: 0,
+            synced: true,
+            original_page_count: 0,
+            journaled: HashSet::new(),
+        }
+    }
+
+    /// Returns whether a hot journal was left next to the database at `db_path`.
+    pub fn is_hot_at(
+        vfs: &dyn Vfs,
+        db_path: &Path,
+        db_file: &dyn VfsFile,
+    ) -> Result<bool, PagerError> {
+        is_hot(vfs, &journal_path(db_path), db_file)
+    }
+
+    /// Rolls back a hot journal left behind by a process that stopped before committing.
+    /// The caller must hold an exclusive lock on the database file. Returns whether a
+    /// journal was rolled back.
+    pub fn recover(
+        vfs: &dyn Vfs,
+        db_path: &Path,
+        db_file: &dyn VfsFile,
+    ) -> Result<bool, PagerError> {
+        let path = journal_path(db_path);
+        if !vfs.exists(&path).map_err(PagerError::JournalFailed)? {
+            return Ok(false);
+        }
+
+        let journal = vfs.open(&path).map_err(PagerError::JournalFailed)?;
+        let rolled_back = roll_back(journal.as_ref(), db_file)?;
+        vfs.delete(&path).map_err(PagerError::JournalFailed)?;
+        Ok(rolled_back)
+    }
+
+    /// Returns whether another connection left a hot journal behind.
+    pub fn is_hot(&self, db_file: &dyn VfsFile) -> Result<bool, PagerError> {
+        if self.is_active() {
+            return Ok(false);
+        }
+
+        is_hot(self.vfs.as_ref(), &self.path, db_file)
+    }
+
+    /// Rolls back a hot journal left behind by another connection. The caller must hold an
+    /// exclusive lock on the database file.
+    pub fn recover_hot(&self, db_file: &dyn VfsFile) -> Result<bool, PagerError> {
+        let journal = self
+            .vfs
+            .open(&self.path)
+            .map_err(PagerError::JournalFailed)?;
+        let rolled_back = roll_back(journal.as_ref(), db_file)?;
+        self.vfs
+            .delete(&self.path)
+            .map_err(PagerError::JournalFailed)?;
+        Ok(rolled_back)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Creates the journal for a transaction that starts from a file of
+    /// `original_page_count` pages. Does nothing when the journal already exists.
+    fn begin(&mut self, original_page_count: usize) -> Result<(), PagerError> {
+        if self.is_active() {
+            return Ok(());
+        }
+
+        let file = self
+            .vfs
+            .open(&self.path)
+            .and_then(|file| file.truncate(0).map(|_| file))
+            .map_err(PagerError::JournalFailed)?;
+
+        let mut header = [0; JOURNAL_HEADER_SIZE];
+        header[..JOURNAL_MAGIC_SIZE].copy_from_slice(JOURNAL_MAGIC);
+        write_u32(
+            &mut header,
+            JOURNAL_PAGE_COUNT_OFFSET,
+            original_page_count as u32,
+        );
+        write_u32(&mut header, JOURNAL_PAGE_SIZE_OFFSET, self.page_size as u32);
+        let checksum = crc32(&header[..JOURNAL_HEADER_CHECKSUM_OFFSET]);
+        write_u32(&mut header, JOURNAL_HEADER_CHECKSUM_OFFSET, checksum);
+        file.write_at(&header, 0)
+            .map_err(PagerError::JournalFailed)?;
+
+        self.file = Some(file);
+        self.len = JOURNAL_HEADER_SIZE;
+        self.synced = false;
+        self.original_page_count = original_page_count;
+        Ok(())
+    }
+
+    /// Saves the original image of `page_num` before it is first changed. Pages that were
+    /// added after the journal started are not saved, rolling back cuts them off instead.
+    pub fn record(
+        &mut self,
+        page_num: usize,
+        page: &Page,
+        original_page_count: usize,
+    ) -> Result<(), PagerError> {
+        self.begin(original_page_count)?;
+
+        if page_num >= self.original_page_count || self.journaled.contains(&page_num) {
+            return Ok(());
+        }
+
+        let checksum_offset = RECORD_IMAGE_OFFSET + self.page_size;
+        let mut record = vec![0; record_size(self.page_size)];
+        write_u32(&mut record, RECORD_PAGE_NUM_OFFSET, page_num as u32);
+        record[RECORD_IMAGE_OFFSET..checksum_offset].copy_from_slice(page.as_slice());
+        let checksum = crc32(&record[..checksum_offset]);
+        write_u32(&mut record, checksum_offset, checksum);
+
+        let file = self.file.as_ref().unwrap();
+        file.write_at(&record, self.len as u64)
+            .map_err(PagerError::JournalFailed)?;
+
+        self.len += record.len();
+        self.synced = false;
+        self.journaled.insert(page_num);
+        Ok(())
+    }
+
+    /// Makes sure the journal exists and every record in it is on disk, unless syncing is
+    /// off. Must be called before any page of the database file is overwritten.
+    pub fn sync(&mut self, original_page_count: usize) -> Result<(), PagerError> {
+        self.begin(original_page_count)?;
+
+        if !self.synced && self.synchronous > Synchronous::Off {
+            let file = self.file.as_ref().unwrap();
+            file.sync().map_err(PagerError::JournalFailed)?;
+            self.synced = true;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the original image of every page changed since the journal was started and
+    /// deletes it.
+    pub fn rollback(&mut self, db_file: &dyn VfsFile) -> Result<(), PagerError> {
+        if let Some(file) = &self.file {
+            roll_back(file.as_ref(), db_file)?;
+        }
+
+        self.commit()
+    }
+
+    /// Deletes the journal once the database file holds every change. This is the point at
+    /// which the transaction is committed.
+    pub fn commit(&mut self) -> Result<(), PagerError> {
+        if self.file.take().is_some() {
+            self.vfs
+                .delete(&self.path)
+                .map_err(PagerError::JournalFailed)?;
+        }
+
+        self.len = 0;
+        self.synced = true;
+        self.journaled.clear();
+        Ok(())
+    }
+}
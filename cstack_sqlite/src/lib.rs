@@ -1,10 +1,20 @@
 pub mod btree;
+pub mod catalog;
+pub mod checksum;
 pub mod cursor;
 pub mod db;
+pub mod freelist;
+pub mod header;
+pub mod journal;
 pub mod meta;
 pub mod page;
 pub mod pager;
 pub mod repl;
 pub mod row;
+pub mod savepoint;
+pub mod schema;
 pub mod statement;
 pub mod table;
+pub mod varint;
+pub mod vfs;
+pub mod wal;
@@ -1,14 +1,66 @@
+use std::{process::ExitCode, time::Duration};
+
 use clap::Parser;
-use cstack_sqlite::repl::REPL;
+use cstack_sqlite::{
+    pager::{
+        is_valid_page_size, JournalMode, PagerConfig, Synchronous, PAGER_DEFAULT_CACHE_SIZE,
+        PAGER_DEFAULT_PAGE_SIZE, PAGER_MAX_PAGE_SIZE, PAGER_MIN_PAGE_SIZE,
+    },
+    repl::REPL,
+};
 
 #[derive(Parser)]
 struct Args {
+    /// Database file, or `:memory:` for a database that is never written to disk
     #[arg(short, long, default_value = "stackqlite.db")]
     filename: String,
+    /// Number of pages kept in memory
+    #[arg(long, default_value_t = PAGER_DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
+    /// How commits are made crash safe: `delete` (rollback journal) or `wal`
+    #[arg(long, default_value = "delete")]
+    journal_mode: JournalMode,
+    /// When commits are synced to disk: `off`, `normal` or `full`
+    #[arg(long, default_value = "full")]
+    synchronous: Synchronous,
+    /// Page size in bytes for a new database, a power of two from 512 to 65536. Existing
+    /// databases keep the page size they were created with
+    #[arg(long, default_value_t = PAGER_DEFAULT_PAGE_SIZE, value_parser = parse_page_size)]
+    page_size: usize,
+    /// Milliseconds to keep retrying when another process has the database locked, before
+    /// giving up with "database is locked"
+    #[arg(long, default_value_t = 0)]
+    busy_timeout: u64,
 }
 
-fn main() -> std::io::Result<()> {
+fn parse_page_size(s: &str) -> Result<usize, String> {
+    let page_size: usize = s.parse().map_err(|e| format!("{e}"))?;
+
+    if !is_valid_page_size(page_size) {
+        return Err(format!(
+            "must be a power of two from {PAGER_MIN_PAGE_SIZE} to {PAGER_MAX_PAGE_SIZE}"
+        ));
+    }
+
+    Ok(page_size)
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
     let repl = REPL::new();
-    repl.start(&args.filename)
+    let config = PagerConfig {
+        cache_size: args.cache_size,
+        journal_mode: args.journal_mode,
+        synchronous: args.synchronous,
+        page_size: args.page_size,
+        busy_timeout: Duration::from_millis(args.busy_timeout),
+        ..PagerConfig::default()
+    };
+
+    if let Err(e) = repl.start(&args.filename, config) {
+        println!("Error: {e}");
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
 }
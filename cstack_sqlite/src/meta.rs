@@ -1,9 +1,71 @@
 pub mod handlers {
+    use std::io::{self, Write};
+
+    use crate::{pager::PagerError, table::Table};
+
     pub enum MetaHandleError {
         UnrecognisedCommand,
+        PagerError(PagerError),
+        OutputError(io::Error),
+    }
+
+    impl From<io::Error> for MetaHandleError {
+        fn from(e: io::Error) -> Self {
+            MetaHandleError::OutputError(e)
+        }
+    }
+
+    impl From<PagerError> for MetaHandleError {
+        fn from(e: PagerError) -> Self {
+            MetaHandleError::PagerError(e)
+        }
+    }
+
+    pub fn handle(
+        input: &str,
+        table: &mut Table,
+        out: &mut dyn Write,
+    ) -> Result<(), MetaHandleError> {
+        match input {
+            ".btree" => {
+                writeln!(out, "Tree:")?;
+                let root_page_num = table.get_root_page_num();
+                print_tree(table, root_page_num, 0, out)?;
+                Ok(())
+            }
+            ".checkpoint" => {
+                table.pager.checkpoint()?;
+                Ok(())
+            }
+            _ => Err(MetaHandleError::UnrecognisedCommand),
+        }
     }
 
-    pub fn handle(_input: &str) -> Result<(), MetaHandleError> {
-        Err(MetaHandleError::UnrecognisedCommand)
+    fn print_tree(
+        table: &mut Table,
+        page_num: usize,
+        indentation_level: usize,
+        out: &mut dyn Write,
+    ) -> Result<(), MetaHandleError> {
+        let indent = "  ".repeat(indentation_level);
+        let node = table.pager.get_page(page_num)?.node();
+
+        if node.is_leaf() {
+            let cell_count = node.get_cell_count();
+            writeln!(out, "{indent}- leaf (size {cell_count})")?;
+            for cell_num in 0..cell_count {
+                writeln!(out, "{indent}  - {}", node.get_cell_key(cell_num))?;
+            }
+            return Ok(());
+        }
+
+        let children = node.get_children();
+        let keys = node.get_keys();
+        writeln!(out, "{indent}- internal (size {})", keys.len())?;
+        for (child, key) in children.iter().zip(keys.iter()) {
+            print_tree(table, *child, indentation_level + 1, out)?;
+            writeln!(out, "{indent}  - key {key}")?;
+        }
+        print_tree(table, children[keys.len()], indentation_level + 1, out)
     }
 }
@@ -1,22 +1,41 @@
-use crate::{
-    btree::node::{Node, NodeError},
-    pager::PAGER_PAGE_SIZE,
-    row::Row,
-    table::Table,
-};
+use crate::btree::node::Node;
+
+pub fn read_u16(raw: &[u8], offset: usize) -> u16 {
+    let bytes: [u8; 2] = raw[offset..(offset + 2)]
+        .try_into()
+        .expect("invalid u16 field");
+
+    u16::from_be_bytes(bytes)
+}
+
+pub fn write_u16(raw: &mut [u8], offset: usize, value: u16) {
+    raw[offset..(offset + 2)].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn read_u32(raw: &[u8], offset: usize) -> u32 {
+    let bytes: [u8; 4] = raw[offset..(offset + 4)]
+        .try_into()
+        .expect("invalid u32 field");
+
+    u32::from_be_bytes(bytes)
+}
+
+pub fn write_u32(raw: &mut [u8], offset: usize, value: u32) {
+    raw[offset..(offset + 4)].copy_from_slice(&value.to_be_bytes());
+}
 
 pub struct Page(Node);
 
 impl Page {
-    pub fn new() -> Self {
-        Self(Node::from(vec![0; PAGER_PAGE_SIZE]))
+    pub fn new(page_size: usize) -> Self {
+        Self(Node::from(vec![0; page_size]))
     }
 
     pub fn cell_count(&self) -> usize {
         self.0.get_cell_count()
     }
 
-    pub fn get_cell_value(&mut self, cell_num: usize) -> &mut [u8] {
+    pub fn get_cell_value(&self, cell_num: usize) -> &[u8] {
         self.0.get_cell_value(cell_num)
     }
 
@@ -24,25 +43,25 @@ impl Page {
         self.0.to_vec_mut()
     }
 
-    pub fn increment_cell_count(&mut self) {}
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
 
     pub fn set_cell_key(&mut self, key: u32, cell_num: usize) {
         self.0.set_cell_key(cell_num, key)
     }
 
-    pub fn insert(
-        &mut self,
-        key: u32,
-        value: &mut Row,
-        table: &mut Table,
-    ) -> Result<(), NodeError> {
-        self.0.insert(key, value, table)
+    pub fn node(&self) -> &Node {
+        &self.0
+    }
+
+    pub fn node_mut(&mut self) -> &mut Node {
+        &mut self.0
     }
 }
 
-impl Clone for Page {
-    fn clone(&self) -> Self {
-        todo!("page cloningin");
-        Self::new()
+impl From<Vec<u8>> for Page {
+    fn from(raw: Vec<u8>) -> Self {
+        Self(Node::from(raw))
     }
 }
@@ -1,88 +1,599 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{Error, ErrorKind},
-    os::unix::fs::FileExt,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Error,
     path::PathBuf,
-    process::exit,
-    sync::Mutex,
+    rc::Rc,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{page::Page, table::TABLE_MAX_PAGES};
-pub const PAGER_PAGE_SIZE: usize = 4096; // 4kb per page - to correspond with fs page size
+use crate::{
+    checksum::crc32,
+    freelist,
+    header::{
+        self, HEADER_CHANGE_COUNTER_OFFSET, HEADER_PAGE_COUNT_OFFSET, HEADER_PAGE_NUM, HEADER_SIZE,
+    },
+    journal::Journal,
+    page::{read_u32, write_u32, Page},
+    savepoint::Savepoint,
+    vfs::{LockMode, OsVfs, Vfs, VfsFile},
+    wal::{wal_path, Wal, WAL_AUTOCHECKPOINT_FRAMES},
+};
+pub const PAGER_DEFAULT_PAGE_SIZE: usize = 4096; // 4kb per page - to correspond with fs page size
+pub const PAGER_MIN_PAGE_SIZE: usize = 512;
+pub const PAGER_MAX_PAGE_SIZE: usize = 65536;
+pub const PAGER_DEFAULT_CACHE_SIZE: usize = 2000; // ~8mb of cached pages at the default size
+
+// how long to sleep between attempts to take a lock another connection holds
+const PAGER_BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// every page ends with a checksum of the bytes before it, checked whenever the page is read
+pub const PAGE_CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Number of bytes of a page left for its content once the checksum trailer is taken off.
+pub const fn usable_size(page_size: usize) -> usize {
+    page_size - PAGE_CHECKSUM_SIZE
+}
+
+/// Page sizes are powers of two from `PAGER_MIN_PAGE_SIZE` to `PAGER_MAX_PAGE_SIZE`.
+pub fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (PAGER_MIN_PAGE_SIZE..=PAGER_MAX_PAGE_SIZE).contains(&page_size)
+}
+
+/// How commits are made crash safe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JournalMode {
+    /// Original page images go to `<db>-journal` before the database file is changed
+    #[default]
+    Delete,
+    /// Changed pages are appended to `<db>-wal` and checkpointed into the database file later
+    Wal,
+}
+
+impl FromStr for JournalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(Self::Delete),
+            "wal" => Ok(Self::Wal),
+            mode => Err(format!("unknown journal mode '{mode}'")),
+        }
+    }
+}
+
+/// How hard commits work to reach the disk before they return, traded off against speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Synchronous {
+    /// Nothing is synced, leaving it to the OS. A crash of the process is survived, a power
+    /// loss can corrupt the database
+    Off,
+    /// Only what crash recovery depends on is synced. In WAL mode commits are not synced, so a
+    /// power loss can lose the latest ones but the log is synced before it is checkpointed.
+    /// With a rollback journal both syncs are needed to stay consistent, so this is `Full`
+    Normal,
+    /// Every commit is on disk before it returns
+    #[default]
+    Full,
+}
+
+impl FromStr for Synchronous {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "normal" => Ok(Self::Normal),
+            "full" => Ok(Self::Full),
+            level => Err(format!("unknown synchronous level '{level}'")),
+        }
+    }
+}
+
+pub struct PagerConfig {
+    /// Maximum number of pages kept in memory before the least recently used one is evicted
+    pub cache_size: usize,
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Size of the pages of a new database. An existing one keeps the size it was created with
+    pub page_size: usize,
+    /// Where the database file, journal and WAL are stored
+    pub vfs: Rc<dyn Vfs>,
+    /// How long to keep retrying when another connection holds a conflicting lock before
+    /// giving up with `PagerError::Busy`
+    pub busy_timeout: Duration,
+}
+
+impl Default for PagerConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: PAGER_DEFAULT_CACHE_SIZE,
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            page_size: PAGER_DEFAULT_PAGE_SIZE,
+            vfs: Rc::new(OsVfs),
+            busy_timeout: Duration::ZERO,
+        }
+    }
+}
+
+struct CachedPage {
+    page: Page,
+    dirty: bool,
+    last_used: u64,
+}
 
 pub struct Pager {
-    pages: Mutex<Vec<Page>>,
+    cache: HashMap<usize, CachedPage>,
+    // cached page numbers keyed by when they were last used, oldest first
+    lru: BTreeMap<u64, usize>,
+    clock: u64,
+    cache_size: usize,
+    page_size: usize,
+    synchronous: Synchronous,
     file_len: usize,
-    file: File,
-    page_count: Mutex<usize>,
+    file: Box<dyn VfsFile>,
+    page_count: usize,
+    // number of pages in the file as of the last commit
+    committed_page_count: usize,
+    journal: Journal,
+    // set in WAL mode, where commits go to the log instead of the database file
+    wal: Option<Wal>,
+    // open savepoints of the current transaction, innermost last
+    savepoints: Vec<Savepoint>,
+    // lock held on the database file, none between statements
+    lock: Option<LockMode>,
+    busy_timeout: Duration,
+    // header change counter as of the last time this connection held a lock
+    change_counter: u32,
 }
 
+#[derive(Debug)]
 pub enum PagerError {
-    FlushInvalidPage,
+    OpenFailed(Error),
+    ReadFailed(Error),
     FlushFailed(Error),
+    JournalFailed(Error),
+    Corrupt { page: usize, reason: String },
+    NotADatabase,
+    UnsupportedFormat(String),
+    Busy,
+    LockFailed(Error),
+}
+
+impl fmt::Display for PagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OpenFailed(e) => write!(f, "unable to open database file: {e}"),
+            Self::ReadFailed(e) => write!(f, "unable to read database file: {e}"),
+            Self::FlushFailed(e) => write!(f, "unable to write database file: {e}"),
+            Self::JournalFailed(e) => write!(f, "unable to use rollback journal: {e}"),
+            Self::Corrupt { page, reason } => {
+                write!(f, "database file is corrupt at page {page}: {reason}")
+            }
+            Self::NotADatabase => write!(f, "file is not a database"),
+            Self::UnsupportedFormat(reason) => {
+                write!(f, "unsupported database format: {reason}")
+            }
+            Self::Busy => write!(f, "database is locked"),
+            Self::LockFailed(e) => write!(f, "unable to lock database file: {e}"),
+        }
+    }
+}
+
+/// Stamps the page's checksum into its trailer and returns the bytes to write out.
+fn seal(page: &mut Page) -> &[u8] {
+    let raw = page.to_vec_mut();
+    let checksum_offset = usable_size(raw.len());
+    let checksum = crc32(&raw[..checksum_offset]);
+    write_u32(raw, checksum_offset, checksum);
+
+    raw
+}
+
+fn write_page(file: &dyn VfsFile, page_num: usize, page: &mut Page) -> Result<(), PagerError> {
+    let offset = (page_num * page.as_slice().len()) as u64;
+
+    file.write_at(seal(page), offset)
+        .map_err(PagerError::FlushFailed)
 }
 
-impl<'a> Pager {
-    pub fn try_new(filename: PathBuf) -> std::io::Result<Self> {
-        let pager_file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(filename)?;
+fn verify_checksum(page_num: usize, raw: &[u8]) -> Result<(), PagerError> {
+    let checksum_offset = usable_size(raw.len());
+    let stored = read_u32(raw, checksum_offset);
+    let computed = crc32(&raw[..checksum_offset]);
 
-        let file_len = pager_file
-            .metadata()
-            .expect("unable to get db file metadata")
-            .len();
+    if stored != computed {
+        return Err(PagerError::Corrupt {
+            page: page_num,
+            reason: format!("checksum mismatch (stored {stored:#010x}, computed {computed:#010x})"),
+        });
+    }
+
+    Ok(())
+}
 
-        let file_len = file_len.try_into().expect("file len is greater than usize");
+/// Raises the lock on the database file from `held` to `mode`, retrying until `busy_timeout`
+/// has passed while another connection holds a conflicting lock.
+fn lock_file(
+    file: &dyn VfsFile,
+    held: Option<LockMode>,
+    mode: LockMode,
+    busy_timeout: Duration,
+) -> Result<(), PagerError> {
+    let started = Instant::now();
 
-        if file_len % PAGER_PAGE_SIZE != 0 {
-            println!("Db file is not a whole number of pages. Corrupt file.");
-            exit(1);
+    while !file.lock(mode).map_err(PagerError::LockFailed)? {
+        if started.elapsed() >= busy_timeout {
+            return Err(PagerError::Busy);
         }
+        // a shared lock taken on the way up is given back while waiting, or the writer
+        // waited on could never commit
+        if held.is_none() {
+            file.unlock(None).map_err(PagerError::LockFailed)?;
+        }
+        thread::sleep(PAGER_BUSY_RETRY_INTERVAL);
+    }
+
+    Ok(())
+}
+
+impl Pager {
+    /// Opens the database, rolling back or checkpointing whatever an earlier connection left
+    /// behind. The pager comes back holding a lock so the caller can finish setting up the
+    /// database, which it releases with `unlock`. It is a shared one unless something had
+    /// to be recovered.
+    pub fn try_new(filename: PathBuf, config: PagerConfig) -> Result<Self, PagerError> {
+        let vfs = config.vfs;
+        let pager_file = vfs.open(&filename).map_err(PagerError::OpenFailed)?;
+        lock_file(
+            pager_file.as_ref(),
+            None,
+            LockMode::Shared,
+            config.busy_timeout,
+        )?;
+        let mut lock = LockMode::Shared;
+
+        // undo whatever a process that stopped partway through a commit left behind
+        if Journal::is_hot_at(vfs.as_ref(), &filename, pager_file.as_ref())? {
+            lock_file(
+                pager_file.as_ref(),
+                Some(LockMode::Shared),
+                LockMode::Exclusive,
+                config.busy_timeout,
+            )?;
+            lock = LockMode::Exclusive;
+            Journal::recover(vfs.as_ref(), &filename, pager_file.as_ref())?;
+        }
+
+        let file_len = pager_file.size().map_err(PagerError::OpenFailed)? as usize;
 
-        Ok(Self {
-            pages: Mutex::new(vec![]),
+        // the page size is fixed when a database is created, a new database whose pages are
+        // all still in the WAL finds it in the log's header
+        let page_size = if file_len > 0 {
+            let mut raw = vec![0; HEADER_SIZE.min(file_len)];
+            pager_file
+                .read_at(&mut raw, 0)
+                .map_err(PagerError::ReadFailed)?;
+            header::read_page_size(&raw)?
+        } else {
+            Wal::read_page_size(vfs.as_ref(), &filename)?.unwrap_or(config.page_size)
+        };
+
+        if !is_valid_page_size(page_size) {
+            return Err(PagerError::UnsupportedFormat(format!(
+                "page size {page_size}"
+            )));
+        }
+
+        let wal = match config.journal_mode {
+            JournalMode::Wal => Some(Wal::open(
+                vfs.as_ref(),
+                &filename,
+                page_size,
+                config.synchronous,
+            )?),
+            JournalMode::Delete => {
+                if vfs
+                    .exists(&wal_path(&filename))
+                    .map_err(PagerError::JournalFailed)?
+                {
+                    lock_file(
+                        pager_file.as_ref(),
+                        Some(lock),
+                        LockMode::Exclusive,
+                        config.busy_timeout,
+                    )?;
+                    lock = LockMode::Exclusive;
+                    Wal::recover(vfs.as_ref(), &filename, pager_file.as_ref(), page_size)?;
+                }
+                None
+            }
+        };
+
+        // checkpointing a WAL left behind can change the length of the file
+        let file_len = pager_file.size().map_err(PagerError::OpenFailed)? as usize;
+
+        if !file_len.is_multiple_of(page_size) {
+            return Err(PagerError::Corrupt {
+                page: file_len / page_size,
+                reason: format!("file size {file_len} is not a whole number of pages"),
+            });
+        }
+
+        let page_count = wal
+            .as_ref()
+            .and_then(Wal::page_count)
+            .unwrap_or(file_len / page_size);
+
+        let mut pager = Self {
+            cache: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            cache_size: config.cache_size.max(1),
+            page_size,
+            synchronous: config.synchronous,
             file_len,
             file: pager_file,
-            page_count: Mutex::new(file_len / PAGER_PAGE_SIZE),
-        })
+            page_count,
+            committed_page_count: page_count,
+            journal: Journal::new(&filename, vfs, page_size, config.synchronous),
+            wal,
+            savepoints: vec![],
+            lock: Some(lock),
+            busy_timeout: config.busy_timeout,
+            change_counter: 0,
+        };
+
+        // page 0 holds the file header and is never handed out for tree nodes
+        if page_count == 0 {
+            header::init(pager.get_page_mut(HEADER_PAGE_NUM)?.to_vec_mut());
+        } else {
+            // checked before the page checksum, so a foreign file is reported as such
+            let mut raw = vec![0; page_size];
+            pager.read_page(HEADER_PAGE_NUM, &mut raw)?;
+            header::validate(&raw, page_count)?;
+            pager.get_page(HEADER_PAGE_NUM)?;
+            pager.change_counter = read_u32(&raw, HEADER_CHANGE_COUNTER_OFFSET);
+        }
+
+        Ok(pager)
+    }
+
+    /// Raises the lock on the database file for the statement or transaction about to run,
+    /// waiting up to the busy timeout for other connections to release theirs. Readers share
+    /// the file with each other and with one writer, until the writer needs it to itself to
+    /// commit. Coming from no lock, whatever other connections committed in the meantime is
+    /// picked up first.
+    pub fn lock(&mut self, mode: LockMode) -> Result<(), PagerError> {
+        self.lock_within(mode, self.busy_timeout)
+    }
+
+    fn lock_within(&mut self, mode: LockMode, busy_timeout: Duration) -> Result<(), PagerError> {
+        let held = self.lock;
+        if held.is_some_and(|held| held >= mode) {
+            return Ok(());
+        }
+
+        if let Err(e) = lock_file(self.file.as_ref(), held, mode, busy_timeout) {
+            // a lock that was raised partway goes back to what was held
+            self.file.unlock(held).map_err(PagerError::LockFailed)?;
+            return Err(e);
+        }
+        self.lock = Some(mode);
+
+        match held {
+            None => {
+                if let Err(e) = self.refresh() {
+                    self.unlock()?;
+                    return Err(e);
+                }
+            }
+            Some(LockMode::Shared) => {
+                // in WAL mode other connections can commit while this one only reads, and
+                // writing on top of what it read would undo their commits
+                let stale = match &mut self.wal {
+                    Some(wal) => wal.refresh()?,
+                    None => false,
+                };
+                if stale {
+                    self.file.unlock(held).map_err(PagerError::LockFailed)?;
+                    self.lock = held;
+                    self.refresh()?;
+                    return Err(PagerError::Busy);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
     }
 
-    pub fn get_page_mut(&self, page_num: usize) -> Page {
-        if page_num > TABLE_MAX_PAGES {
-            panic!("Tried to fetch page number out of bounds. max_page: {TABLE_MAX_PAGES} page_num: {page_num}");
+    /// Releases the lock once the statement or transaction holding it has committed or
+    /// rolled back.
+    pub fn unlock(&mut self) -> Result<(), PagerError> {
+        if self.lock.take().is_some() {
+            self.file.unlock(None).map_err(PagerError::LockFailed)?;
         }
 
-        let mut pages = self.pages.lock().unwrap();
-        let mut page_count = self.page_count.lock().unwrap();
+        Ok(())
+    }
+
+    /// The lock needed to write pages out. A WAL is appended to while readers go on reading,
+    /// but with a rollback journal the pages they read are overwritten.
+    fn write_lock_mode(&self) -> LockMode {
+        match self.wal {
+            Some(_) => LockMode::Reserved,
+            None => LockMode::Exclusive,
+        }
+    }
 
-        if self.page_exists(page_num) {
-            return pages.get(page_num).unwrap().clone();
+    /// Rolls back a journal a crashed writer left behind and drops the cached pages when
+    /// another connection committed since this one last held a lock, which the change
+    /// counter in the header tells.
+    fn refresh(&mut self) -> Result<(), PagerError> {
+        if self.wal.is_none() && self.journal.is_hot(self.file.as_ref())? {
+            lock_file(
+                self.file.as_ref(),
+                self.lock,
+                LockMode::Exclusive,
+                self.busy_timeout,
+            )?;
+            self.lock = Some(LockMode::Exclusive);
+            self.journal.recover_hot(self.file.as_ref())?;
         }
 
-        pages.insert(page_num, Page::new());
-        let mut page = pages.get(page_num).unwrap().clone();
+        let wal_changed = match &mut self.wal {
+            Some(wal) => wal.refresh()?,
+            None => false,
+        };
 
-        // REFACTOR: check if there's a math.ceil alternative to acheive this
+        let file_len = self.file.size().map_err(PagerError::ReadFailed)? as usize;
+        let page_count = self
+            .wal
+            .as_ref()
+            .and_then(Wal::page_count)
+            .unwrap_or(file_len / self.page_size);
 
-        if page_num <= *page_count {
-            let page_offset = (page_num * PAGER_PAGE_SIZE) as u64;
+        let mut raw = vec![0; self.page_size];
+        self.read_page(HEADER_PAGE_NUM, &mut raw)?;
+        let change_counter = read_u32(&raw, HEADER_CHANGE_COUNTER_OFFSET);
+
+        if wal_changed || change_counter != self.change_counter {
+            self.cache.clear();
+            self.lru.clear();
+            self.file_len = file_len;
+            self.page_count = page_count;
+            self.committed_page_count = page_count;
+            self.change_counter = change_counter;
+        }
 
-            match self.file.read_exact_at(&mut page.to_vec_mut(), page_offset) {
-                // assumption here is that if we get `ErrorKind::UnexpectedEof` here it means the page is empty
-                // so its fine
-                Err(e) if e.kind() != ErrorKind::UnexpectedEof => panic!("unable to read page"),
-                _ => {}
+        Ok(())
+    }
+
+    /// Returns a page for reading, loading it into the cache if needed.
+    pub fn get_page(&mut self, page_num: usize) -> Result<&Page, PagerError> {
+        Ok(&self.load(page_num)?.page)
+    }
+
+    /// Returns a page for writing. The page is marked dirty so the change is written back to
+    /// the file when it is evicted or flushed. Outside WAL mode its original image is
+    /// journaled first.
+    pub fn get_page_mut(&mut self, page_num: usize) -> Result<&mut Page, PagerError> {
+        self.load(page_num)?;
+
+        let cached = self.cache.get_mut(&page_num).unwrap();
+        if self.wal.is_none() {
+            self.journal
+                .record(page_num, &cached.page, self.committed_page_count)?;
+        }
+        if let Some(savepoint) = self.savepoints.last_mut() {
+            savepoint.record(page_num, &cached.page);
+        }
+        cached.dirty = true;
+        Ok(&mut cached.page)
+    }
+
+    /// Brings a page into the cache, from the WAL when it holds the page and from the file
+    /// otherwise. Pages read have their checksum verified, so a damaged page is reported as
+    /// corrupt instead of being handed to the tree.
+    fn load(&mut self, page_num: usize) -> Result<&mut CachedPage, PagerError> {
+        if !self.cache.contains_key(&page_num) {
+            if self.cache.len() >= self.cache_size {
+                self.evict()?;
+            }
+
+            let mut raw = vec![0; self.page_size];
+            let on_disk = page_num < self.page_count;
+
+            if on_disk {
+                self.read_page(page_num, &mut raw)?;
+                verify_checksum(page_num, &raw)?;
+            } else {
+                self.page_count = page_num + 1;
+            }
+
+            let cached = CachedPage {
+                page: Page::from(raw),
+                // a page past the end of the file must be written out even if it never changes
+                dirty: !on_disk,
+                last_used: 0,
+            };
+            self.cache.insert(page_num, cached);
+        }
+
+        self.clock += 1;
+        let cached = self.cache.get_mut(&page_num).unwrap();
+        self.lru.remove(&cached.last_used);
+        self.lru.insert(self.clock, page_num);
+        cached.last_used = self.clock;
+
+        Ok(cached)
+    }
+
+    /// Reads the committed image of a page into `raw`, from the WAL when it holds the page
+    /// and from the file otherwise.
+    fn read_page(&self, page_num: usize, raw: &mut [u8]) -> Result<(), PagerError> {
+        match &self.wal {
+            Some(wal) if wal.contains(page_num) => wal.read_page(page_num, raw),
+            _ => {
+                let page_offset = (page_num * self.page_size) as u64;
+
+                self.file
+                    .read_at(raw, page_offset)
+                    .map_err(PagerError::ReadFailed)
             }
         }
+    }
 
-        if page_num >= *page_count {
-            *page_count += 1;
+    /// Drops the least recently used page from the cache, writing it back first if dirty.
+    /// In WAL mode it goes to the log as part of the commit in progress. A page that cannot
+    /// be written stays cached so nothing is lost.
+    fn evict(&mut self) -> Result<(), PagerError> {
+        let Some((_, page_num)) = self.lru.first_key_value() else {
+            return Ok(());
+        };
+        if self.cache[page_num].dirty {
+            self.lock(self.write_lock_mode())?;
         }
 
-        page
+        let (last_used, page_num) = self.lru.pop_first().unwrap();
+        let cached = self.cache.get_mut(&page_num).unwrap();
+        if cached.dirty {
+            let written = match &mut self.wal {
+                Some(wal) => wal.write_frame(page_num, seal(&mut cached.page), None),
+                None => self
+                    .journal
+                    .sync(self.committed_page_count)
+                    .and_then(|_| write_page(self.file.as_ref(), page_num, &mut cached.page)),
+            };
+            if let Err(e) = written {
+                self.lru.insert(last_used, page_num);
+                return Err(e);
+            }
+        }
+
+        self.cache.remove(&page_num);
+        Ok(())
+    }
+
+    /// Returns the page number a newly allocated page should use. Pages on the freelist are
+    /// reused first, otherwise the new page goes at the end of the file.
+    pub fn get_unused_page_num(&mut self) -> Result<usize, PagerError> {
+        Ok(freelist::pop(self)?.unwrap_or(self.page_count))
+    }
+
+    /// Hands a page that is no longer referenced by the tree back to the freelist. Its content
+    /// is cleared so nothing stale can be read from it.
+    pub fn free_page(&mut self, page_num: usize) -> Result<(), PagerError> {
+        *self.get_page_mut(page_num)? = Page::new(self.page_size);
+        freelist::push(self, page_num)
+    }
+
+    pub fn get_free_page_count(&mut self) -> Result<usize, PagerError> {
+        freelist::count(self)
     }
 
     pub fn get_file_len(&self) -> usize {
@@ -90,22 +601,201 @@ impl<'a> Pager {
     }
 
     pub fn page_exists(&self, page_num: usize) -> bool {
-        self.pages.lock().unwrap().get(page_num).is_some()
+        self.cache.contains_key(&page_num)
     }
 
-    pub fn flush_page(&mut self, page_num: usize) -> Result<(), PagerError> {
-        if let Some(page) = self.pages.lock().unwrap().get_mut(page_num) {
-            let offset = (page_num * PAGER_PAGE_SIZE) as u64;
+    /// Commits every change since the last flush. The journal is synced before the database
+    /// file is written, and deleting it once the file is synced is what makes the commit
+    /// final. The pages stay cached and are clean afterwards. A commit that cannot get the
+    /// lock it needs fails with `PagerError::Busy` before anything is written, and can be
+    /// tried again.
+    pub fn flush(&mut self) -> Result<(), PagerError> {
+        let changed = self.journal.is_active()
+            || self.wal.as_ref().is_some_and(Wal::has_uncommitted)
+            || self.cache.values().any(|cached| cached.dirty);
+
+        if changed {
+            self.lock(self.write_lock_mode())?;
+        }
+        self.savepoints.clear();
+
+        if changed {
+            let change_counter = header::read_field(self, HEADER_CHANGE_COUNTER_OFFSET)? as u32;
+            self.change_counter = change_counter.wrapping_add(1);
+            header::write_field(
+                self,
+                HEADER_CHANGE_COUNTER_OFFSET,
+                self.change_counter as usize,
+            )?;
+            header::write_field(self, HEADER_PAGE_COUNT_OFFSET, self.page_count)?;
+        }
+
+        if self.wal.is_some() {
+            return self.flush_to_wal();
+        }
 
-            self.file
-                .write_all_at(&page.to_vec_mut()[..PAGER_PAGE_SIZE], offset)
-                .map_err(|e| PagerError::FlushFailed(e))?;
+        let mut dirty_pages: Vec<_> = self
+            .cache
+            .iter_mut()
+            .filter(|(_, cached)| cached.dirty)
+            .collect();
+
+        if dirty_pages.is_empty() && !self.journal.is_active() {
+            return Ok(());
         }
 
-        Err(PagerError::FlushInvalidPage)
+        self.journal.sync(self.committed_page_count)?;
+        dirty_pages.sort_unstable_by_key(|(page_num, _)| **page_num);
+
+        for (page_num, cached) in dirty_pages {
+            write_page(self.file.as_ref(), *page_num, &mut cached.page)?;
+            cached.dirty = false;
+        }
+
+        // pages past the end that were written before a rollback to a savepoint are cut off
+        self.file
+            .truncate((self.page_count * self.page_size) as u64)
+            .map_err(PagerError::FlushFailed)?;
+        if self.synchronous > Synchronous::Off {
+            self.file.sync().map_err(PagerError::FlushFailed)?;
+        }
+        self.journal.commit()?;
+        self.committed_page_count = self.page_count;
+
+        Ok(())
+    }
+
+    /// Appends every dirty page to the WAL, the last one as the commit frame. The header page
+    /// is always among them since every commit bumps its change counter.
+    fn flush_to_wal(&mut self) -> Result<(), PagerError> {
+        let mut dirty_pages: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(_, cached)| cached.dirty)
+            .map(|(page_num, _)| *page_num)
+            .collect();
+
+        if dirty_pages.is_empty() {
+            return Ok(());
+        }
+        dirty_pages.sort_unstable();
+
+        let wal = self.wal.as_mut().unwrap();
+        let last = dirty_pages.len() - 1;
+        for (i, page_num) in dirty_pages.iter().enumerate() {
+            let cached = self.cache.get_mut(page_num).unwrap();
+            let commit = (i == last).then_some(self.page_count);
+            wal.write_frame(*page_num, seal(&mut cached.page), commit)?;
+            cached.dirty = false;
+        }
+
+        self.committed_page_count = self.page_count;
+
+        // the checkpoint is left for a later commit while other connections are reading
+        if wal.frame_count() >= WAL_AUTOCHECKPOINT_FRAMES {
+            match self.lock_within(LockMode::Exclusive, Duration::ZERO) {
+                Ok(()) => self.checkpoint()?,
+                Err(PagerError::Busy) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Throws away every change since the last commit. Cached pages are dropped, and pages
+    /// that already reached the database file or the WAL are put back the way they were.
+    pub fn rollback(&mut self) -> Result<(), PagerError> {
+        match &mut self.wal {
+            Some(wal) => wal.rollback()?,
+            None => self.journal.rollback(self.file.as_ref())?,
+        }
+
+        self.cache.clear();
+        self.lru.clear();
+        self.page_count = self.committed_page_count;
+        self.savepoints.clear();
+
+        Ok(())
+    }
+
+    /// Starts a savepoint called `name` inside the current transaction.
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints
+            .push(Savepoint::new(name.to_string(), self.page_count));
+    }
+
+    /// Forgets the newest savepoint called `name` and every savepoint created after it,
+    /// keeping their changes. Returns false when there is no such savepoint.
+    pub fn release(&mut self, name: &str) -> bool {
+        let Some(index) = self.savepoints.iter().rposition(|s| s.name() == name) else {
+            return false;
+        };
+
+        for savepoint in self.savepoints.split_off(index) {
+            match self.savepoints.last_mut() {
+                Some(parent) => parent.absorb(savepoint),
+                None => break,
+            }
+        }
+
+        true
+    }
+
+    /// Undoes every change made since the newest savepoint called `name` was created. The
+    /// savepoints created after it are dropped while it stays open. Returns false when there
+    /// is no such savepoint.
+    pub fn rollback_to(&mut self, name: &str) -> Result<bool, PagerError> {
+        let Some(index) = self.savepoints.iter().rposition(|s| s.name() == name) else {
+            return Ok(false);
+        };
+
+        // older savepoints hold older images, so they are applied last
+        let mut images = HashMap::new();
+        for savepoint in self.savepoints[index..].iter_mut().rev() {
+            images.extend(savepoint.take_images());
+        }
+        self.savepoints.truncate(index + 1);
+
+        let page_count = self.savepoints[index].page_count();
+        let dropped: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(page_num, _)| **page_num >= page_count)
+            .map(|(page_num, cached)| (*page_num, cached.last_used))
+            .collect();
+        for (page_num, last_used) in dropped {
+            self.cache.remove(&page_num);
+            self.lru.remove(&last_used);
+        }
+        self.page_count = page_count;
+
+        // a nested savepoint can hold pages that did not exist yet when this one was created
+        for (page_num, image) in images.into_iter().filter(|(p, _)| *p < page_count) {
+            let cached = self.load(page_num)?;
+            cached.page = Page::from(image);
+            cached.dirty = true;
+        }
+
+        Ok(true)
+    }
+
+    /// Copies the committed frames in the WAL back into the database file and empties the
+    /// log, once no other connection is reading. Does nothing outside WAL mode.
+    pub fn checkpoint(&mut self) -> Result<(), PagerError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+
+        self.lock(LockMode::Exclusive)?;
+        self.wal.as_mut().unwrap().checkpoint(self.file.as_ref())
     }
 
     pub fn get_page_count(&self) -> usize {
-        *self.page_count.lock().unwrap()
+        self.page_count
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
     }
 }
@@ -1,26 +1,33 @@
-use crate::db::{Database, DatabaseError, HandleDBQueryStatusCode};
-use std::{
-    io::{stdin, stdout, Write},
-    process::exit,
+use crate::{
+    db::{Database, DatabaseError, HandleDBQueryStatusCode},
+    pager::PagerConfig,
 };
+use std::io::{stdin, stdout, Write};
 
 pub struct REPL;
 
+impl Default for REPL {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl REPL {
     pub fn new() -> Self {
         Self
     }
 
-    pub fn start(&self, db_filename: &str) -> std::io::Result<()> {
-        let mut db = Database::try_new(db_filename)?;
+    pub fn start(&self, db_filename: &str, config: PagerConfig) -> Result<(), DatabaseError> {
+        let mut db = Database::try_new_with_config(db_filename, config)?;
         'repl: loop {
             self.prompt();
-            if let Some(input) = self.read_input() {
-                match db.handle_query(&input) {
-                    Ok(HandleDBQueryStatusCode::Exit) => break 'repl,
-                    Ok(HandleDBQueryStatusCode::Continue) => continue,
-                    Err(DatabaseError::CloseError) => exit(1),
-                }
+            let Some(input) = self.read_input() else {
+                break 'repl;
+            };
+
+            match db.handle_query(&input)? {
+                HandleDBQueryStatusCode::Exit => break 'repl,
+                HandleDBQueryStatusCode::Continue => continue,
             }
         }
 
@@ -29,13 +36,16 @@ impl REPL {
 
     fn prompt(&self) {
         let mut writer = stdout();
-        let _ = writer.write("csquarelite> ".as_bytes());
+        let _ = writer.write(b"csquarelite> ");
         let _ = writer.flush();
     }
 
+    /// Returns `None` once stdin is closed so the loop doesn't spin on empty reads.
     fn read_input(&self) -> Option<String> {
         let mut buf = String::new();
-        let _ = stdin().read_line(&mut buf);
-        Some(buf)
+        match stdin().read_line(&mut buf) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(buf),
+        }
     }
 }
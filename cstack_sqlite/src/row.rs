@@ -1,91 +1,302 @@
-const ID_SIZE: usize = std::mem::size_of::<u32>();
-const ID_OFFSET: usize = 0;
-const USERNAME_SIZE: usize = 32;
-const USERNAME_OFFSET: usize = ID_OFFSET + ID_SIZE;
-const EMAIL_SIZE: usize = 255;
-const EMAIL_OFFSET: usize = USERNAME_OFFSET + USERNAME_SIZE;
-pub const ROW_SIZE: usize = ID_SIZE + EMAIL_SIZE + USERNAME_SIZE;
-
-#[derive(Debug)]
+use std::fmt;
+
+use crate::{schema::Schema, varint};
+
+// Record Serial Types
+const SERIAL_TYPE_NULL: u64 = 0;
+// big-endian two's complement integers of 1, 2, 3, 4, 6 and 8 bytes
+const SERIAL_TYPE_INT8: u64 = 1;
+const SERIAL_TYPE_INT64: u64 = 6;
+const SERIAL_TYPE_INTEGER_SIZES: [usize; 6] = [1, 2, 3, 4, 6, 8];
+// a big-endian IEEE 754 double
+const SERIAL_TYPE_REAL: u64 = 7;
+const SERIAL_TYPE_REAL_SIZE: usize = 8;
+// the integers 0 and 1, which need no body
+const SERIAL_TYPE_ZERO: u64 = 8;
+const SERIAL_TYPE_ONE: u64 = 9;
+// even types from 12 are blobs and odd types from 13 are texts of (type - 12) / 2 bytes
+const SERIAL_TYPE_BLOB: u64 = 12;
+const SERIAL_TYPE_TEXT: u64 = 13;
+
+/// A value of one column. Like in SQLite, a column's type doesn't restrict the values it
+/// holds, it only decides which of these storage classes a value is converted to, when
+/// that loses nothing.
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// Parses a literal: `null`, an integer, a real, a `'quoted'` text where `''` stands
+    /// for a quote, or a blob written in hexadecimal as `x'0a1b'`. Any other word is a
+    /// text of its own. Returns `None` for quoted literals that are malformed.
+    pub fn parse(token: &str) -> Option<Self> {
+        if token.eq_ignore_ascii_case("null") {
+            return Some(Self::Null);
+        }
+        if let Some(hex) = token
+            .strip_prefix(['x', 'X'])
+            .and_then(|rest| rest.strip_prefix('\''))
+        {
+            return Self::parse_blob(hex.strip_suffix('\'')?).map(Self::Blob);
+        }
+        if let Some(quoted) = token.strip_prefix('\'') {
+            let text = quoted.strip_suffix('\'')?;
+            // every quote inside has to be doubled
+            if text.replace("''", "").contains('\'') {
+                return None;
+            }
+            return Some(Self::Text(text.replace("''", "'")));
+        }
+
+        Some(Self::parse_number(token).unwrap_or_else(|| Self::Text(token.to_string())))
+    }
+
+    /// Parses `s` as an integer, or as a real when it has a fraction or an exponent or is
+    /// too large for an `i64`.
+    pub fn parse_number(s: &str) -> Option<Self> {
+        if let Ok(value) = s.parse() {
+            return Some(Self::Integer(value));
+        }
+
+        // rules out the words f64 parses, like "inf" and "nan"
+        let is_decimal = s
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'))
+            && s.contains(|c: char| c.is_ascii_digit());
+        match s.parse::<f64>() {
+            Ok(value) if is_decimal && value.is_finite() => Some(Self::Real(value)),
+            _ => None,
+        }
+    }
+
+    fn parse_blob(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return None;
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..(i + 2)], 16).ok())
+            .collect()
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "NULL"),
+            Self::Integer(value) => write!(f, "{value:?}"),
+            Self::Real(value) => write!(f, "{value:?}"),
+            Self::Text(value) => write!(f, "{value:?}"),
+            Self::Blob(value) => {
+                write!(f, "x'")?;
+                for byte in value {
+                    write!(f, "{byte:02x}")?;
+                }
+                write!(f, "'")
+            }
+        }
+    }
+}
+
+/// The values of one row, in the order of the columns of its table's schema. The first one
+/// is the row's key.
 pub struct Row {
-    pub id: u32,
-    username: String,
-    email: String,
+    values: Vec<Value>,
 }
 
 pub enum RowSerializationError {
     StringTooLong { field: String },
+    InvalidString { field: String },
+    InvalidRecord,
 }
 
 impl Row {
-    pub fn new(id: u32, username: String, email: String) -> Self {
-        Self {
-            id,
-            username,
-            email,
+    pub fn new(values: Vec<Value>) -> Self {
+        Self { values }
+    }
+
+    pub fn key(&self) -> u32 {
+        match self.values[0] {
+            // keys are checked to fit when they are parsed
+            Value::Integer(key) => key as u32,
+            _ => unreachable!("the first column of a table is an integer key"),
         }
     }
 
-    fn serialize_string_column(
-        column_name: &str,
-        column_value: &str,
-        column_size: usize,
-        offset: usize,
-        dest: &mut [u8],
-    ) -> Result<(), RowSerializationError> {
-        let mut column_buf = vec![0; column_size];
-        let column_value_len = column_value.as_bytes().len();
-        if column_value_len > column_size {
-            return Err(RowSerializationError::StringTooLong {
-                field: column_name.to_string(),
+    pub fn value(&self, column: usize) -> &Value {
+        &self.values[column]
+    }
+
+    pub fn set_value(&mut self, column: usize, value: Value) {
+        self.values[column] = value;
+    }
+
+    /// Pairs the row with the schema it belongs to, so it can be printed with its column
+    /// names.
+    pub fn with_schema<'a>(&'a self, schema: &'a Schema) -> SchemaRow<'a> {
+        SchemaRow { row: self, schema }
+    }
+
+    /// Encodes the row as a record: a header holding its own size and the serial type of
+    /// every column, all as varints, followed by the body of every column packed one after
+    /// the other. Values only take up the bytes they need, whatever their column allows.
+    pub fn serialize(&self, schema: &Schema) -> Result<Vec<u8>, RowSerializationError> {
+        let mut serial_types = vec![];
+        let mut body = vec![];
+
+        for (column, value) in schema.columns().iter().zip(&self.values) {
+            match value {
+                Value::Null => serial_types.push(SERIAL_TYPE_NULL),
+                Value::Integer(value) => {
+                    let value = *value;
+                    let serial_type = integer_serial_type(value);
+                    let size = serial_type_size(serial_type);
+                    body.extend_from_slice(&value.to_be_bytes()[(8 - size)..]);
+                    serial_types.push(serial_type);
+                }
+                Value::Real(value) => {
+                    body.extend_from_slice(&value.to_be_bytes());
+                    serial_types.push(SERIAL_TYPE_REAL);
+                }
+                Value::Text(value) => {
+                    if let Some(max_len) = column.max_len() {
+                        if value.len() > max_len {
+                            return Err(RowSerializationError::StringTooLong {
+                                field: column.name().to_string(),
+                            });
+                        }
+                    }
+                    body.extend_from_slice(value.as_bytes());
+                    serial_types.push(SERIAL_TYPE_TEXT + 2 * value.len() as u64);
+                }
+                Value::Blob(value) => {
+                    body.extend_from_slice(value);
+                    serial_types.push(SERIAL_TYPE_BLOB + 2 * value.len() as u64);
+                }
+            }
+        }
+
+        let types_len: usize = serial_types.iter().map(|t| varint::len(*t)).sum();
+        // the header size counts the varint that holds it
+        let mut header_len = types_len + 1;
+        while varint::len(header_len as u64) + types_len > header_len {
+            header_len += 1;
+        }
+
+        let mut record = Vec::with_capacity(header_len + body.len());
+        varint::write(&mut record, header_len as u64);
+        for serial_type in serial_types {
+            varint::write(&mut record, serial_type);
+        }
+        record.extend(body);
+
+        Ok(record)
+    }
+
+    /// Decodes a record `serialize` wrote with the same schema. Each value is decoded as the
+    /// storage class its serial type says, whichever column it is in.
+    pub fn deserialize(schema: &Schema, src: &[u8]) -> Result<Self, RowSerializationError> {
+        let (header_len, mut header_offset) =
+            varint::read(src).ok_or(RowSerializationError::InvalidRecord)?;
+        let header = src
+            .get(..header_len as usize)
+            .ok_or(RowSerializationError::InvalidRecord)?;
+        let mut body_offset = header.len();
+        let mut values = vec![];
+
+        for column in schema.columns() {
+            let (serial_type, len) = header
+                .get(header_offset..)
+                .and_then(varint::read)
+                .ok_or(RowSerializationError::InvalidRecord)?;
+            header_offset += len;
+
+            let size = serial_type_size(serial_type);
+            let body = src
+                .get(body_offset..(body_offset + size))
+                .ok_or(RowSerializationError::InvalidRecord)?;
+            body_offset += size;
+
+            values.push(match serial_type {
+                SERIAL_TYPE_NULL => Value::Null,
+                SERIAL_TYPE_ZERO => Value::Integer(0),
+                SERIAL_TYPE_ONE => Value::Integer(1),
+                SERIAL_TYPE_INT8..=SERIAL_TYPE_INT64 => {
+                    // sign-extend the big-endian two's complement body to 8 bytes
+                    let fill = if body[0] & 0x80 == 0 { 0 } else { 0xff };
+                    let mut bytes = [fill; 8];
+                    bytes[(8 - size)..].copy_from_slice(body);
+                    Value::Integer(i64::from_be_bytes(bytes))
+                }
+                SERIAL_TYPE_REAL => Value::Real(f64::from_be_bytes(
+                    body.try_into().expect("a real takes up 8 bytes"),
+                )),
+                t if t >= SERIAL_TYPE_BLOB && t % 2 == 0 => Value::Blob(body.to_vec()),
+                t if t >= SERIAL_TYPE_TEXT => {
+                    Value::Text(Self::deserialize_string_column(column.name(), body)?)
+                }
+                // 10 and 11 are reserved
+                _ => return Err(RowSerializationError::InvalidRecord),
             });
         }
-        let (msbytes, lsbytes) = column_buf.split_at_mut(column_value_len);
-        msbytes.copy_from_slice(column_value.as_bytes());
 
-        let column_buf = [msbytes, lsbytes].concat();
-        dest[offset..(offset + column_size)].copy_from_slice(&column_buf[..]);
-        Ok(())
+        Ok(Self::new(values))
+    }
+
+    fn deserialize_string_column(
+        column_name: &str,
+        src: &[u8],
+    ) -> Result<String, RowSerializationError> {
+        std::str::from_utf8(src).map(str::to_string).map_err(|_| {
+            RowSerializationError::InvalidString {
+                field: column_name.to_string(),
+            }
+        })
     }
+}
 
-    fn serialize_integer_column(
-        column_value: u32,
-        column_size: usize,
-        offset: usize,
-        dest: &mut [u8],
-    ) {
-        dest[offset..(offset + column_size)].copy_from_slice(&column_value.to_be_bytes());
+/// The serial type of the smallest encoding of `value`. 0 and 1 have types of their own that
+/// take up no body at all.
+fn integer_serial_type(value: i64) -> u64 {
+    match value {
+        0 => SERIAL_TYPE_ZERO,
+        1 => SERIAL_TYPE_ONE,
+        _ => (SERIAL_TYPE_INT8..=SERIAL_TYPE_INT64)
+            .find(|serial_type| {
+                let bits = 8 * serial_type_size(*serial_type) as u32;
+                bits == i64::BITS || (-(1 << (bits - 1))..(1 << (bits - 1))).contains(&value)
+            })
+            .expect("every i64 fits in 8 bytes"),
     }
+}
 
-    pub fn serialize(&mut self, dest: &mut [u8]) -> Result<(), RowSerializationError> {
-        Self::serialize_integer_column(self.id, ID_SIZE, ID_OFFSET, dest);
-        Self::serialize_string_column(
-            "username",
-            &self.username,
-            USERNAME_SIZE,
-            USERNAME_OFFSET,
-            dest,
-        )?;
-        Self::serialize_string_column("email", &self.email, EMAIL_SIZE, EMAIL_OFFSET, dest)
+/// Bytes the body of a column of `serial_type` takes up.
+fn serial_type_size(serial_type: u64) -> usize {
+    match serial_type {
+        SERIAL_TYPE_INT8..=SERIAL_TYPE_INT64 => SERIAL_TYPE_INTEGER_SIZES[serial_type as usize - 1],
+        SERIAL_TYPE_REAL => SERIAL_TYPE_REAL_SIZE,
+        t if t >= SERIAL_TYPE_BLOB => ((t - SERIAL_TYPE_BLOB) / 2) as usize,
+        _ => 0,
     }
+}
 
-    pub fn deserialize(src: &[u8]) -> Self {
-        // TODO: handle extra null bytes better
-        let id: [u8; 4] = src[ID_OFFSET..ID_SIZE]
-            .try_into()
-            .expect("could not deserialize id");
-        let id = u32::from_be_bytes(id);
-
-        let username =
-            String::from_utf8(src[USERNAME_OFFSET..(USERNAME_OFFSET + USERNAME_SIZE)].to_vec())
-                .expect("could not deserialize username")
-                .trim_matches(char::from(0))
-                .to_string();
-        let email = String::from_utf8(src[EMAIL_OFFSET..(EMAIL_OFFSET + EMAIL_SIZE)].to_vec())
-            .expect("could not deserialize email")
-            .trim_matches(char::from(0))
-            .to_string();
-
-        Self::new(id, username, email)
+pub struct SchemaRow<'a> {
+    row: &'a Row,
+    schema: &'a Schema,
+}
+
+impl fmt::Debug for SchemaRow<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut row = f.debug_struct("Row");
+        for (column, value) in self.schema.columns().iter().zip(&self.row.values) {
+            row.field(column.name(), value);
+        }
+        row.finish()
     }
 }
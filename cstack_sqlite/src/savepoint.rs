@@ -0,0 +1,54 @@
+//! Savepoints mark a point inside a transaction that can be rolled back to without
+//! dropping the whole transaction. Each one keeps, in memory, the image every page had when
+//! it was first changed after the savepoint was created, as long as no newer savepoint was
+//! created before that change.
+use std::collections::HashMap;
+
+use crate::page::Page;
+
+pub struct Savepoint {
+    name: String,
+    // number of pages when the savepoint was created, pages past it are simply dropped
+    page_count: usize,
+    images: HashMap<usize, Vec<u8>>,
+}
+
+impl Savepoint {
+    pub fn new(name: String, page_count: usize) -> Self {
+        Self {
+            name,
+            page_count,
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    /// Keeps the image of `page_num` before it is first changed under this savepoint.
+    pub fn record(&mut self, page_num: usize, page: &Page) {
+        if page_num < self.page_count && !self.images.contains_key(&page_num) {
+            self.images.insert(page_num, page.as_slice().to_vec());
+        }
+    }
+
+    /// Takes over the images of a savepoint nested inside this one when it is released. An
+    /// image this savepoint does not have yet is also the page's image from when this one
+    /// was created, since the page had not changed in between.
+    pub fn absorb(&mut self, nested: Savepoint) {
+        for (page_num, image) in nested.images {
+            if page_num < self.page_count {
+                self.images.entry(page_num).or_insert(image);
+            }
+        }
+    }
+
+    pub fn take_images(&mut self) -> HashMap<usize, Vec<u8>> {
+        std::mem::take(&mut self.images)
+    }
+}
@@ -0,0 +1,289 @@
+//! Table definitions. A table is declared with `create table`, whose text is what the catalog
+//! keeps, and its rows are stored as records of the values of its columns.
+use crate::{row::Value, statement::StatementError};
+
+/// The table every database has, which statements that name no table work on.
+pub const USERS_TABLE_SQL: &str =
+    "create table users (id integer, username text(32), email text(255))";
+
+/// How values are converted when they are stored in a column, picked from the column's
+/// declared type by the same rules as SQLite's type affinity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// Declared types containing `int`
+    Integer,
+    /// Declared types containing `char`, `clob` or `text`
+    Text,
+    /// Declared types containing `blob`, or no type at all
+    Blob,
+    /// Declared types containing `real`, `floa` or `doub`
+    Real,
+    /// Any other declared type
+    Numeric,
+}
+
+impl Affinity {
+    fn of(type_name: &str) -> Self {
+        let type_name = type_name.to_ascii_lowercase();
+        let contains = |parts: &[&str]| parts.iter().any(|part| type_name.contains(part));
+
+        if contains(&["int"]) {
+            Self::Integer
+        } else if contains(&["char", "clob", "text"]) {
+            Self::Text
+        } else if type_name.is_empty() || contains(&["blob"]) {
+            Self::Blob
+        } else if contains(&["real", "floa", "doub"]) {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    /// Converts `value` to the storage class the affinity prefers, when that loses nothing.
+    /// Numeric affinities turn text that reads as a number into one, and text affinity turns
+    /// numbers into text. Other values are stored as they are.
+    pub fn apply(self, value: Value) -> Value {
+        match (self, value) {
+            (Self::Text, Value::Integer(value)) => Value::Text(value.to_string()),
+            (Self::Text, Value::Real(value)) => Value::Text(format!("{value:?}")),
+            (Self::Integer | Self::Real | Self::Numeric, Value::Text(text)) => {
+                match Value::parse_number(text.trim()) {
+                    Some(number) => self.apply(number),
+                    None => Value::Text(text),
+                }
+            }
+            (Self::Integer | Self::Numeric, Value::Real(value)) => {
+                // only reals that are whole numbers in the range of an i64 are exact integers
+                if value.fract() == 0.0
+                    && (-9.223_372_036_854_775e18..9.223_372_036_854_775e18).contains(&value)
+                {
+                    Value::Integer(value as i64)
+                } else {
+                    Value::Real(value)
+                }
+            }
+            (Self::Real, Value::Integer(value)) => Value::Real(value as f64),
+            (_, value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    name: String,
+    affinity: Affinity,
+    // longest text the column holds, in bytes, when its type declares a length
+    max_len: Option<usize>,
+}
+
+impl Column {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn affinity(&self) -> Affinity {
+        self.affinity
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Parses the declared type of a column, a type name optionally followed by one or two
+    /// numbers in parentheses. Any name is accepted, since it only picks the affinity. A single
+    /// number such as in `text(32)` is the longest text the column holds, while two such as in
+    /// `decimal(10,2)` are a precision and scale that SQLite ignores, and so does this.
+    fn parse_type(name: &str, type_name: &str) -> Option<Self> {
+        let (type_name, max_len) = match type_name.split_once('(') {
+            Some((type_name, args)) => {
+                let args = args
+                    .strip_suffix(')')?
+                    .split(',')
+                    .map(|arg| arg.parse::<usize>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                match args[..] {
+                    [len] => (type_name, Some(len).filter(|len| *len > 0)?.into()),
+                    [_, _] => (type_name, None),
+                    _ => return None,
+                }
+            }
+            None => (type_name, None),
+        };
+        if !type_name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            affinity: Affinity::of(type_name),
+            max_len,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    name: String,
+    columns: Vec<Column>,
+    // the statement the table was created with
+    sql: String,
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits the column definitions of a table on the commas between them, leaving alone the ones
+/// inside a type's parentheses such as in `decimal(10,2)`.
+fn split_definitions(body: &str) -> Vec<&str> {
+    let mut definitions = vec![];
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                definitions.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    definitions.push(&body[start..]);
+    definitions
+}
+
+impl Schema {
+    pub fn users() -> Self {
+        Self::parse(USERS_TABLE_SQL).expect("the users table definition is valid")
+    }
+
+    /// Parses `create table <name> (<column> <type>, ...)`. The first column must be an
+    /// integer, which is the key rows are stored and looked up by.
+    pub fn parse(sql: &str) -> Result<Self, StatementError> {
+        let syntax_error = || {
+            StatementError::SynthaxError(
+                "expected 'create table <name> (<column> <type>, ...)'".to_string(),
+            )
+        };
+
+        let sql = sql.trim();
+        let (name, body) = sql
+            .strip_prefix("create table ")
+            .and_then(|rest| rest.split_once('('))
+            .ok_or_else(syntax_error)?;
+        let body = body.trim_end().strip_suffix(')').ok_or_else(syntax_error)?;
+
+        let name = name.trim();
+        if !is_identifier(name) {
+            return Err(StatementError::ValidationError(format!(
+                "Invalid table name '{name}'"
+            )));
+        }
+
+        let mut columns: Vec<Column> = vec![];
+        for definition in split_definitions(body) {
+            let mut tokens = definition.split_whitespace();
+            let (Some(column_name), column_type) = (tokens.next(), tokens.collect::<String>())
+            else {
+                return Err(StatementError::SynthaxError(format!(
+                    "invalid column definition '{}'",
+                    definition.trim()
+                )));
+            };
+
+            if !is_identifier(column_name) {
+                return Err(StatementError::ValidationError(format!(
+                    "Invalid column name '{column_name}'"
+                )));
+            }
+            if columns.iter().any(|column| column.name == column_name) {
+                return Err(StatementError::ValidationError(format!(
+                    "Duplicate column '{column_name}'"
+                )));
+            }
+            let column = Column::parse_type(column_name, &column_type).ok_or_else(|| {
+                StatementError::ValidationError(format!(
+                    "Unknown type '{column_type}' for column '{column_name}'"
+                ))
+            })?;
+
+            columns.push(column);
+        }
+
+        if columns[0].affinity != Affinity::Integer {
+            return Err(StatementError::ValidationError(format!(
+                "Key column '{}' must be an integer",
+                columns[0].name
+            )));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            columns,
+            sql: sql.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub fn key_column(&self) -> &Column {
+        &self.columns[0]
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    /// Parses the literal `token` as a value of the column at `column`, converted by the
+    /// column's affinity.
+    pub fn parse_value(&self, column: usize, token: &str) -> Result<Value, StatementError> {
+        if column == 0 {
+            return self.parse_key(token).map(|key| Value::Integer(key.into()));
+        }
+
+        let column = &self.columns[column];
+        let value = Value::parse(token).ok_or_else(|| {
+            StatementError::ValidationError(format!("Invalid value {token} for '{}'", column.name))
+        })?;
+
+        Ok(column.affinity.apply(value))
+    }
+
+    /// Parses `token` as a key. The tree is keyed by `u32`, so unlike SQLite's 64-bit rowids a
+    /// key has to be an integer from 0 to `u32::MAX`, and any other integer is refused rather
+    /// than wrapped.
+    pub fn parse_key(&self, token: &str) -> Result<u32, StatementError> {
+        let column = self.key_column();
+        let value = Value::parse(token).map(|value| column.affinity.apply(value));
+
+        let reason = match value {
+            Some(Value::Integer(key)) => match u32::try_from(key) {
+                Ok(key) => return Ok(key),
+                Err(_) if key < 0 => "cannot be negative",
+                Err(_) => "is too large for a key",
+            },
+            _ => "is not a valid integer",
+        };
+
+        Err(StatementError::ValidationError(format!(
+            "Integer value for '{}' {reason}",
+            column.name
+        )))
+    }
+}
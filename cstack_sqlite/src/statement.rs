@@ -1,15 +1,60 @@
+use std::{
+    io::{self, Write},
+    iter::Peekable,
+    ops::{Bound, RangeBounds},
+};
+
 use crate::{
     btree::node::NodeError,
+    catalog::{Catalog, DEFAULT_TABLE},
     cursor::Cursor,
-    row::{Row, RowSerializationError},
-    table::Table,
+    pager::PagerError,
+    row::{Row, RowSerializationError, Value},
+    schema::Schema,
+    table::{Table, TableError},
 };
 
+/// The ids a where clause matches, as start and end bounds on the key.
+pub type KeyRange = (Bound<u32>, Bound<u32>);
+
+// statements on rows carry the name of the table they work on
 pub enum Statement {
-    Select,
-    Insert { row: Row },
+    Select {
+        table: String,
+        range: KeyRange,
+    },
+    Delete {
+        table: String,
+        range: KeyRange,
+    },
+    Update {
+        table: String,
+        range: KeyRange,
+        // column index and new value of every column that is set
+        assignments: Vec<(usize, Value)>,
+    },
+    Insert {
+        table: String,
+        row: Row,
+    },
+    CreateTable {
+        schema: Schema,
+    },
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint {
+        name: String,
+    },
+    Release {
+        name: String,
+    },
+    RollbackTo {
+        name: String,
+    },
 }
 
+#[derive(Debug)]
 pub enum StatementError {
     UnrecognisedStatement,
     SynthaxError(String),
@@ -17,77 +62,505 @@ pub enum StatementError {
 }
 
 pub enum ExecuteError {
-    TableFull,
+    DuplicateKey,
     SerializationFail(String),
+    PagerError(PagerError),
+    TransactionError(String),
+    OutputError(io::Error),
+    SchemaError(String),
+    StatementError(StatementError),
+}
+
+impl From<NodeError> for ExecuteError {
+    fn from(e: NodeError) -> Self {
+        match e {
+            NodeError::SerializationError(se) => match se {
+                RowSerializationError::StringTooLong { field } => {
+                    ExecuteError::SerializationFail(format!("String value for '{field}' too long."))
+                }
+                RowSerializationError::InvalidString { field } => {
+                    ExecuteError::SerializationFail(format!("String value for '{field}' invalid."))
+                }
+                RowSerializationError::InvalidRecord => {
+                    ExecuteError::SerializationFail("Row invalid.".to_string())
+                }
+            },
+            NodeError::CellTooLarge { size, max_size } => ExecuteError::SerializationFail(format!(
+                "Row too long: {size} bytes, a page holds at most {max_size}."
+            )),
+        }
+    }
+}
+
+impl From<io::Error> for ExecuteError {
+    fn from(e: io::Error) -> Self {
+        ExecuteError::OutputError(e)
+    }
+}
+
+impl From<StatementError> for ExecuteError {
+    fn from(e: StatementError) -> Self {
+        ExecuteError::StatementError(e)
+    }
+}
+
+impl From<PagerError> for ExecuteError {
+    fn from(e: PagerError) -> Self {
+        ExecuteError::PagerError(e)
+    }
+}
+
+impl From<TableError> for ExecuteError {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::PagerError(e) => ExecuteError::PagerError(e),
+            TableError::NodeError(e) => e.into(),
+        }
+    }
 }
 
 impl Statement {
-    pub fn new(token: &str) -> Result<Self, StatementError> {
-        Self::parse_token_to_statement(token)
+    /// Parses `token` into a statement. The table it names, or the users table when it names
+    /// none, is looked up in `catalog` for the columns values are checked against.
+    pub fn new(token: &str, catalog: &Catalog) -> Result<Self, StatementError> {
+        Self::parse_token_to_statement(token, catalog)
     }
 
-    fn parse_token_to_statement(s: &str) -> Result<Self, StatementError> {
+    fn parse_token_to_statement(s: &str, catalog: &Catalog) -> Result<Self, StatementError> {
         Ok(match s {
             t if t.starts_with("insert") => {
-                let mut tokens = t.split(" ");
+                let mut tokens = Self::tokenize(t).into_iter().peekable();
+                tokens.next();
+                let schema = Self::parse_table(&mut tokens, "into", catalog)?;
+
+                let mut values = vec![];
+                for (i, column) in schema.columns().iter().enumerate() {
+                    let token = tokens.next().ok_or(StatementError::SynthaxError(format!(
+                        "invalid {}",
+                        column.name()
+                    )))?;
+                    values.push(schema.parse_value(i, token)?);
+                }
+                if let Some(t) = tokens.next() {
+                    return Err(StatementError::SynthaxError(format!(
+                        "unexpected '{t}' after the last column"
+                    )));
+                }
+
+                Statement::Insert {
+                    table: schema.name().to_string(),
+                    row: Row::new(values),
+                }
+            }
+            t if t.starts_with("select") => {
+                let mut tokens = Self::tokenize(t).into_iter().peekable();
                 tokens.next();
+                let schema = Self::parse_table(&mut tokens, "from", catalog)?;
 
-                let id = tokens
-                    .next()
-                    .ok_or(StatementError::SynthaxError("invalid id".to_string()))?
-                    .parse::<u32>()
-                    .map_err(|_| {
-                        StatementError::ValidationError(
-                            "Integer value for 'id' cannot be negative".to_string(),
-                        )
-                    })?;
-
-                let username = tokens
-                    .next()
-                    .ok_or(StatementError::SynthaxError("invalid username".to_string()))?;
-                let email = tokens
-                    .next()
-                    .ok_or(StatementError::SynthaxError("invalid email".to_string()))?;
-                let row = Row::new(id, username.to_string(), email.to_string());
-                Statement::Insert { row }
-            }
-            t if t.starts_with("select") => Statement::Select,
+                let range = match tokens.next() {
+                    None => (Bound::Unbounded, Bound::Unbounded),
+                    Some("where") => Self::parse_predicate(tokens, schema)?,
+                    Some(t) => {
+                        return Err(StatementError::SynthaxError(format!(
+                            "unexpected '{t}' after select"
+                        )))
+                    }
+                };
+                Statement::Select {
+                    table: schema.name().to_string(),
+                    range,
+                }
+            }
+            t if t.starts_with("delete") => {
+                let mut tokens = Self::tokenize(t).into_iter().peekable();
+                tokens.next();
+                let schema = Self::parse_table(&mut tokens, "from", catalog)?;
+
+                if tokens.next() != Some("where") {
+                    return Err(Self::predicate_syntax_error(schema));
+                }
+                let range = Self::parse_predicate(tokens, schema)?;
+                Statement::Delete {
+                    table: schema.name().to_string(),
+                    range,
+                }
+            }
+            t if t.starts_with("update") => {
+                // update [<table>] set <column> = <value>[, <column> = <value>] where <key> <op> <key>
+                let tokens = Self::tokenize(t);
+                let (schema, rest) = match &tokens[1..] {
+                    ["set", rest @ ..] => (Self::lookup_table(DEFAULT_TABLE, catalog)?, rest),
+                    [name, "set", rest @ ..] => (Self::lookup_table(name, catalog)?, rest),
+                    _ => {
+                        return Err(StatementError::SynthaxError(
+                            "expected 'set' after update".to_string(),
+                        ))
+                    }
+                };
+                let where_index = rest
+                    .iter()
+                    .position(|&t| t == "where")
+                    .ok_or_else(|| Self::predicate_syntax_error(schema))?;
+
+                let assignments = rest[..where_index]
+                    .split(|&t| t == ",")
+                    .map(|assignment| Self::parse_assignment(assignment, schema))
+                    .collect::<Result<_, _>>()?;
+
+                let range = Self::parse_predicate(rest[where_index + 1..].iter().copied(), schema)?;
+                Statement::Update {
+                    table: schema.name().to_string(),
+                    range,
+                    assignments,
+                }
+            }
+            t if t.starts_with("create table") => Statement::CreateTable {
+                schema: Schema::parse(t)?,
+            },
+            t if t.starts_with("savepoint") => Statement::Savepoint {
+                name: Self::parse_savepoint_name(&t["savepoint".len()..])?,
+            },
+            t if t.starts_with("release") => Statement::Release {
+                name: Self::parse_savepoint_name(&t["release".len()..])?,
+            },
+            t if t.starts_with("rollback to") => Statement::RollbackTo {
+                name: Self::parse_savepoint_name(&t["rollback to".len()..])?,
+            },
+            "begin" | "begin transaction" => Statement::Begin,
+            "commit" | "commit transaction" | "end" => Statement::Commit,
+            "rollback" | "rollback transaction" => Statement::Rollback,
             _ => return Err(StatementError::UnrecognisedStatement),
         })
     }
 
-    pub fn execute(&mut self, table: &mut Table) -> Result<(), ExecuteError> {
+    /// Parses the `<keyword> <table>` naming the table a statement works on and returns the
+    /// table's schema. A statement that names no table works on the users table.
+    fn parse_table<'a, 'c>(
+        tokens: &mut Peekable<impl Iterator<Item = &'a str>>,
+        keyword: &str,
+        catalog: &'c Catalog,
+    ) -> Result<&'c Schema, StatementError> {
+        if tokens.next_if_eq(&keyword).is_none() {
+            return Self::lookup_table(DEFAULT_TABLE, catalog);
+        }
+
+        let name = tokens.next().ok_or(StatementError::SynthaxError(format!(
+            "expected a table name after '{keyword}'"
+        )))?;
+        Self::lookup_table(name, catalog)
+    }
+
+    fn lookup_table<'c>(name: &str, catalog: &'c Catalog) -> Result<&'c Schema, StatementError> {
+        catalog
+            .table(name)
+            .map(|(_, schema)| schema)
+            .ok_or(StatementError::ValidationError(format!(
+                "Unknown table '{name}'"
+            )))
+    }
+
+    /// Returns the name of the table a statement on rows works on.
+    pub fn table(&self) -> Option<&str> {
         match self {
-            Self::Insert { row } => Self::execute_insert(row, table),
-            Self::Select => Self::execute_select(table),
+            Self::Select { table, .. }
+            | Self::Delete { table, .. }
+            | Self::Update { table, .. }
+            | Self::Insert { table, .. } => Some(table),
+            _ => None,
         }
     }
 
-    fn execute_insert(row: &mut Row, mut table: &mut Table) -> Result<(), ExecuteError> {
-        let cursor = Cursor::end(&mut table);
-        let mut page = table.pager.get_page_mut(cursor.page());
+    /// Splits a statement into words, commas and comparison operators. A quoted text is one
+    /// word with its quotes, whatever spaces, commas or operators are inside, and a quote
+    /// left open runs to the end of the statement.
+    fn tokenize(s: &str) -> Vec<&str> {
+        let is_operator = |c: char| matches!(c, '=' | '<' | '>' | '!');
+        let mut tokens = vec![];
+        let mut chars = s.char_indices().peekable();
 
-        page.insert(row.id, row, &mut table).map_err(|e| match e {
-            NodeError::SerializationError(se) => match se {
-                RowSerializationError::StringTooLong { field } => {
-                    ExecuteError::SerializationFail(format!("String value for '{field}' too long."))
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+
+            let mut end = start + c.len_utf8();
+            if is_operator(c) {
+                while let Some((i, c)) = chars.next_if(|&(_, c)| is_operator(c)) {
+                    end = i + c.len_utf8();
                 }
-            },
-            NodeError::NodeFullInsertError => ExecuteError::TableFull,
-        })?;
+            } else if c != ',' {
+                let mut quoted = c == '\'';
+                while let Some((i, c)) = chars
+                    .next_if(|&(_, c)| quoted || !(c.is_whitespace() || c == ',' || is_operator(c)))
+                {
+                    // a doubled quote closes and reopens the text
+                    if c == '\'' {
+                        quoted = !quoted;
+                    }
+                    end = i + c.len_utf8();
+                }
+            }
+            tokens.push(&s[start..end]);
+        }
+
+        tokens
+    }
+
+    /// Parses the tokens of one `<column> = <value>` of an update into the column's index and
+    /// new value. The key column cannot be set, since rows are stored by it.
+    fn parse_assignment(
+        assignment: &[&str],
+        schema: &Schema,
+    ) -> Result<(usize, Value), StatementError> {
+        let [column, "=", value] = *assignment else {
+            return Err(StatementError::SynthaxError(format!(
+                "invalid assignment '{}'",
+                assignment.join(" ")
+            )));
+        };
+
+        match schema.column_index(column) {
+            Some(0) => Err(StatementError::ValidationError(format!(
+                "Column '{column}' cannot be updated"
+            ))),
+            Some(index) => Ok((index, schema.parse_value(index, value)?)),
+            None => Err(StatementError::ValidationError(format!(
+                "Unknown column '{column}'"
+            ))),
+        }
+    }
+
+    /// Parses the `[savepoint] <name>` that follows `savepoint`, `release` and `rollback to`.
+    fn parse_savepoint_name(s: &str) -> Result<String, StatementError> {
+        match s.split_whitespace().collect::<Vec<_>>()[..] {
+            [name] | ["savepoint", name] => Ok(name.to_string()),
+            _ => Err(StatementError::SynthaxError(
+                "expected a savepoint name".to_string(),
+            )),
+        }
+    }
+
+    fn predicate_syntax_error(schema: &Schema) -> StatementError {
+        let key = schema.key_column().name();
+        StatementError::SynthaxError(format!("expected 'where {key} <op> <{key}>'"))
+    }
+
+    /// Parses the conditions of a where clause, `<key> <op> <key>` comparisons on the key
+    /// column joined by `and`, into the range of keys they all allow.
+    fn parse_predicate<'a>(
+        mut tokens: impl Iterator<Item = &'a str>,
+        schema: &Schema,
+    ) -> Result<KeyRange, StatementError> {
+        let mut range = (Bound::Unbounded, Bound::Unbounded);
+
+        loop {
+            let (Some(column), Some(op), Some(value)) =
+                (tokens.next(), tokens.next(), tokens.next())
+            else {
+                return Err(Self::predicate_syntax_error(schema));
+            };
+            if column != schema.key_column().name() {
+                return Err(Self::predicate_syntax_error(schema));
+            }
+
+            let key = schema.parse_key(value)?;
+
+            let (start, end) = match op {
+                "=" => (Bound::Included(key), Bound::Included(key)),
+                ">" => (Bound::Excluded(key), Bound::Unbounded),
+                ">=" => (Bound::Included(key), Bound::Unbounded),
+                "<" => (Bound::Unbounded, Bound::Excluded(key)),
+                "<=" => (Bound::Unbounded, Bound::Included(key)),
+                op => {
+                    return Err(StatementError::SynthaxError(format!(
+                        "unknown operator '{op}'"
+                    )))
+                }
+            };
+            range = (
+                Self::tighter_start(range.0, start),
+                Self::tighter_end(range.1, end),
+            );
+
+            match tokens.next() {
+                None => return Ok(range),
+                Some("and") => continue,
+                Some(t) => {
+                    return Err(StatementError::SynthaxError(format!(
+                        "unexpected '{t}' in where clause"
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Of two start bounds, keeps the one that lets fewer keys through.
+    fn tighter_start(a: Bound<u32>, b: Bound<u32>) -> Bound<u32> {
+        match (a, b) {
+            (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+            (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.max(y)),
+            (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.max(y)),
+            (Bound::Included(x), Bound::Excluded(y)) | (Bound::Excluded(y), Bound::Included(x)) => {
+                if x > y {
+                    Bound::Included(x)
+                } else {
+                    Bound::Excluded(y)
+                }
+            }
+        }
+    }
+
+    /// Of two end bounds, keeps the one that lets fewer keys through.
+    fn tighter_end(a: Bound<u32>, b: Bound<u32>) -> Bound<u32> {
+        match (a, b) {
+            (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+            (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.min(y)),
+            (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.min(y)),
+            (Bound::Included(x), Bound::Excluded(y)) | (Bound::Excluded(y), Bound::Included(x)) => {
+                if x < y {
+                    Bound::Included(x)
+                } else {
+                    Bound::Excluded(y)
+                }
+            }
+        }
+    }
+
+    /// Runs the statement against `table`, whose rows are laid out by `schema`. A select
+    /// writes the rows it finds to `out`.
+    pub fn execute(
+        &self,
+        table: &mut Table,
+        schema: &Schema,
+        out: &mut dyn Write,
+    ) -> Result<(), ExecuteError> {
+        match self {
+            Self::Insert { row, .. } => Self::execute_insert(row, table, schema),
+            Self::Select { range, .. } => Self::execute_select(range, table, schema, out),
+            Self::Delete { range, .. } => Self::execute_delete(range, table),
+            Self::Update {
+                range, assignments, ..
+            } => Self::execute_update(range, assignments, table, schema),
+            Self::CreateTable { .. } => {
+                unreachable!("create table is handled by the database")
+            }
+            Self::Begin
+            | Self::Commit
+            | Self::Rollback
+            | Self::Savepoint { .. }
+            | Self::Release { .. }
+            | Self::RollbackTo { .. } => {
+                unreachable!("transaction statements are handled by the database")
+            }
+        }
+    }
 
+    fn execute_insert(row: &Row, table: &mut Table, schema: &Schema) -> Result<(), ExecuteError> {
+        let cursor = table.find(row.key())?;
+
+        if !cursor.end_of_table() {
+            let page = table.pager.get_page(cursor.page())?;
+            if page.node().get_cell_key(cursor.cell_num()) == row.key() {
+                return Err(ExecuteError::DuplicateKey);
+            }
+        }
+
+        table.insert(&cursor, row, schema)?;
+
+        Ok(())
+    }
+
+    /// Collects the keys inside `range` up front, so rows can be changed or removed without
+    /// a cursor being moved around by the rebalancing that causes.
+    fn keys_in_range(range: &KeyRange, table: &mut Table) -> Result<Vec<u32>, PagerError> {
+        let mut keys = vec![];
+        let mut cursor = Cursor::seek(table, range.start_bound().cloned())?;
+
+        while !cursor.end_of_table() {
+            let node = table.pager.get_page(cursor.page())?.node();
+            let key = node.get_cell_key(cursor.cell_num());
+            if !range.contains(&key) {
+                break;
+            }
+            keys.push(key);
+            cursor.advance(table)?;
+        }
+
+        Ok(keys)
+    }
+
+    /// Reads the row `cursor` points at. A cell that does not hold a valid row can only come
+    /// from a damaged page, so it is reported as corruption of that page.
+    fn read_row(table: &mut Table, cursor: &Cursor, schema: &Schema) -> Result<Row, PagerError> {
+        let page = table.pager.get_page(cursor.page())?;
+
+        Row::deserialize(schema, page.get_cell_value(cursor.cell_num())).map_err(|e| {
+            let reason = match e {
+                RowSerializationError::StringTooLong { field }
+                | RowSerializationError::InvalidString { field } => {
+                    format!("cell {} has an invalid '{field}'", cursor.cell_num())
+                }
+                RowSerializationError::InvalidRecord => {
+                    format!("cell {} is not a valid record", cursor.cell_num())
+                }
+            };
+            PagerError::Corrupt {
+                page: cursor.page(),
+                reason,
+            }
+        })
+    }
+
+    fn execute_delete(range: &KeyRange, table: &mut Table) -> Result<(), ExecuteError> {
+        for key in Self::keys_in_range(range, table)? {
+            if let Some(cursor) = table.find_key(key)? {
+                table.delete(&cursor)?;
+            }
+        }
         Ok(())
     }
 
-    fn execute_select(mut table: &mut Table) -> Result<(), ExecuteError> {
-        let mut cursor = Cursor::start(&mut table);
+    fn execute_update(
+        range: &KeyRange,
+        assignments: &[(usize, Value)],
+        table: &mut Table,
+        schema: &Schema,
+    ) -> Result<(), ExecuteError> {
+        for key in Self::keys_in_range(range, table)? {
+            let Some(cursor) = table.find_key(key)? else {
+                continue;
+            };
+
+            let mut row = Self::read_row(table, &cursor, schema)?;
+            for (column, value) in assignments {
+                row.set_value(*column, value.clone());
+            }
+
+            table.update(&cursor, &row, schema)?;
+        }
+
+        Ok(())
+    }
+
+    /// Seeks to the start of `range` and walks the leaves in key order until the first key
+    /// past its end, so only the rows in range are read.
+    fn execute_select(
+        range: &KeyRange,
+        table: &mut Table,
+        schema: &Schema,
+        out: &mut dyn Write,
+    ) -> Result<(), ExecuteError> {
+        let mut cursor = Cursor::seek(table, range.start_bound().cloned())?;
 
         while !cursor.end_of_table() {
-            let mut page = table.pager.get_page_mut(cursor.page());
-            let value = page.get_cell_value(cursor.cell_num());
-            let row = Row::deserialize(value);
-            println!("{:?}", row);
-            cursor.advance(&mut table);
+            let page = table.pager.get_page(cursor.page())?;
+            if !range.contains(&page.node().get_cell_key(cursor.cell_num())) {
+                break;
+            }
+
+            let row = Self::read_row(table, &cursor, schema)?;
+            writeln!(out, "{:?}", row.with_schema(schema))?;
+            cursor.advance(table)?;
         }
         Ok(())
     }
@@ -1,5 +1,16 @@
-use crate::pager::{Pager, PagerError};
-pub const TABLE_MAX_PAGES: usize = 100;
+use crate::{
+    btree::{
+        layout::{
+            internal_node_max_cells, internal_node_min_cells, leaf_node_capacity,
+            leaf_node_min_fill, LEAF_NODE_CELL_POINTER_SIZE,
+        },
+        node::{leaf_cell, Node, NodeError},
+    },
+    cursor::Cursor,
+    pager::{Pager, PagerError},
+    row::Row,
+    schema::Schema,
+};
 
 pub struct Table {
     pub pager: Pager,
@@ -7,29 +18,515 @@ pub struct Table {
 }
 
 pub enum TableError {
-    FlushError(PagerError),
+    PagerError(PagerError),
+    NodeError(NodeError),
 }
 
-impl<'a> Table {
-    pub fn new(pager: Pager) -> Self {
-        Self {
+impl From<PagerError> for TableError {
+    fn from(e: PagerError) -> Self {
+        TableError::PagerError(e)
+    }
+}
+
+impl From<NodeError> for TableError {
+    fn from(e: NodeError) -> Self {
+        TableError::NodeError(e)
+    }
+}
+
+/// Bytes `cells` take up in a leaf, counting their pointers.
+fn leaf_cells_size(cells: &[Vec<u8>]) -> usize {
+    cells
+        .iter()
+        .map(|cell| cell.len() + LEAF_NODE_CELL_POINTER_SIZE)
+        .sum()
+}
+
+/// Picks where to share `cells` between two leaves, returning how many go to the left one.
+/// Both have to fit, and of the ways they do the one splitting the bytes most evenly wins.
+fn leaf_split_point(cells: &[Vec<u8>], page_size: usize) -> usize {
+    let capacity = leaf_node_capacity(page_size);
+    let total = leaf_cells_size(cells);
+    let mut left = 0;
+
+    (1..cells.len())
+        .filter_map(|count| {
+            left += cells[count - 1].len() + LEAF_NODE_CELL_POINTER_SIZE;
+            let right = total - left;
+            (left <= capacity && right <= capacity).then_some((count, left.abs_diff(right)))
+        })
+        .min_by_key(|(_, imbalance)| *imbalance)
+        .map(|(count, _)| count)
+        .expect("cells of at most half a leaf always fit in two leaves")
+}
+
+impl Table {
+    pub fn new(mut pager: Pager) -> Result<Self, PagerError> {
+        // page 0 is the pager's metadata page so the tree starts right after it
+        let root_page_num = 1;
+
+        if pager.get_page_count() <= root_page_num {
+            // New database file. Initialize the root page as an empty leaf node.
+            pager.get_page_mut(root_page_num)?.node_mut().set_root(true);
+        }
+
+        Ok(Self {
             pager,
-            root_page_num: 0,
+            root_page_num,
+        })
+    }
+
+    pub fn flush_pages(&mut self) -> Result<(), PagerError> {
+        self.pager.flush()
+    }
+
+    pub fn get_root_page_num(&self) -> usize {
+        self.root_page_num
+    }
+
+    /// Starts a new, empty tree and returns its root page, which it keeps for good.
+    pub fn create_tree(&mut self) -> Result<usize, PagerError> {
+        let page_size = self.pager.page_size();
+        let root_page_num = self.pager.get_unused_page_num()?;
+        let node = self.pager.get_page_mut(root_page_num)?.node_mut();
+        *node = Node::new_leaf(page_size);
+        node.set_root(true);
+
+        Ok(root_page_num)
+    }
+
+    /// Runs `f` against the tree rooted at `root_page_num`, such as the catalog's, instead of
+    /// the table's own.
+    pub fn with_tree<T>(&mut self, root_page_num: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let own_root_page_num = std::mem::replace(&mut self.root_page_num, root_page_num);
+        let result = f(self);
+        self.root_page_num = own_root_page_num;
+        result
+    }
+
+    /// Returns a cursor at the cell holding `key`, or at the position it would be inserted
+    /// at when the key is missing. Every level is binary searched on the way down, so a
+    /// lookup only reads one page per level of the tree.
+    pub fn find(&mut self, key: u32) -> Result<Cursor, PagerError> {
+        let mut page_num = self.root_page_num;
+
+        loop {
+            let node = self.pager.get_page(page_num)?.node();
+
+            if node.is_leaf() {
+                let cell_num = node.find_cell(key);
+                return Ok(Cursor::new(
+                    page_num,
+                    cell_num,
+                    cell_num >= node.get_cell_count(),
+                ));
+            }
+
+            page_num = node.find_child(key);
         }
     }
 
-    pub fn flush_pages(&mut self) -> Result<(), TableError> {
-        for page_num in 0..self.pager.get_page_count() {
-            if self.pager.page_exists(page_num) {
-                self.pager
-                    .flush_page(page_num)
-                    .map_err(|e| TableError::FlushError(e))?;
+    /// Like `find`, but only returns a cursor when a cell with exactly `key` exists.
+    pub fn find_key(&mut self, key: u32) -> Result<Option<Cursor>, PagerError> {
+        let cursor = self.find(key)?;
+
+        if cursor.end_of_table() {
+            return Ok(None);
+        }
+
+        let node = self.pager.get_page(cursor.page())?.node();
+        Ok((node.get_cell_key(cursor.cell_num()) == key).then_some(cursor))
+    }
+
+    /// Inserts `value` under its key at the position pointed to by `cursor`, splitting the
+    /// leaf (and any ancestors that fill up as a result) when it has no room left.
+    pub fn insert(
+        &mut self,
+        cursor: &Cursor,
+        value: &Row,
+        schema: &Schema,
+    ) -> Result<(), TableError> {
+        let cell = leaf_cell(value.key(), value, schema, self.pager.page_size())?;
+        self.insert_cell(cursor, cell)?;
+
+        Ok(())
+    }
+
+    fn insert_cell(&mut self, cursor: &Cursor, cell: Vec<u8>) -> Result<(), PagerError> {
+        let node = self.pager.get_page_mut(cursor.page())?.node_mut();
+
+        if node.has_room_for(&cell) {
+            node.insert_cell(cursor.cell_num(), &cell);
+            return Ok(());
+        }
+
+        self.split_leaf_and_insert(cursor, cell)
+    }
+
+    fn split_leaf_and_insert(&mut self, cursor: &Cursor, cell: Vec<u8>) -> Result<(), PagerError> {
+        let mut cells = self.pager.get_page(cursor.page())?.node().get_cells();
+        cells.insert(cursor.cell_num(), cell);
+
+        self.split_leaf(cursor.page(), cells)
+    }
+
+    /// Shares `cells` between the leaf on `old_page_num` and a new leaf to its right. The new
+    /// leaf is set up before the old one changes, so failing to get a page for it leaves the
+    /// old leaf as it was.
+    fn split_leaf(&mut self, old_page_num: usize, cells: Vec<Vec<u8>>) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let new_page_num = self.pager.get_unused_page_num()?;
+        *self.pager.get_page_mut(new_page_num)?.node_mut() = Node::new_leaf(page_size);
+
+        let old_node = self.pager.get_page_mut(old_page_num)?.node_mut();
+        let (left_cells, right_cells) = cells.split_at(leaf_split_point(&cells, page_size));
+        old_node.set_cells(left_cells);
+        let separator = old_node.get_cell_key(left_cells.len() - 1);
+        let parent_page_num = old_node.get_parent();
+        let next_leaf = old_node.get_next_leaf();
+        old_node.set_next_leaf(new_page_num);
+
+        let new_node = self.pager.get_page_mut(new_page_num)?.node_mut();
+        new_node.set_cells(right_cells);
+        new_node.set_parent(parent_page_num);
+        new_node.set_next_leaf(next_leaf);
+
+        self.insert_into_parent(old_page_num, separator, new_page_num)
+    }
+
+    /// Records `right_page_num` as the sibling directly after `left_page_num` in their parent,
+    /// where `separator` is the largest key that stays under `left_page_num`.
+    fn insert_into_parent(
+        &mut self,
+        left_page_num: usize,
+        separator: u32,
+        right_page_num: usize,
+    ) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let left_node = self.pager.get_page(left_page_num)?.node();
+
+        if left_node.is_root() {
+            return self.create_new_root(separator, right_page_num);
+        }
+
+        let parent_page_num = left_node.get_parent();
+        let parent_node = self.pager.get_page_mut(parent_page_num)?.node_mut();
+        let mut children = parent_node.get_children();
+        let mut keys = parent_node.get_keys();
+
+        let child_index = children
+            .iter()
+            .position(|child| *child == left_page_num)
+            .expect("split node is missing from its parent");
+        children.insert(child_index + 1, right_page_num);
+        keys.insert(child_index, separator);
+
+        if keys.len() <= internal_node_max_cells(page_size) {
+            parent_node.set_entries(&children, &keys);
+            self.pager
+                .get_page_mut(right_page_num)?
+                .node_mut()
+                .set_parent(parent_page_num);
+            return Ok(());
+        }
+
+        self.split_internal(parent_page_num, children, keys)
+    }
+
+    fn split_internal(
+        &mut self,
+        page_num: usize,
+        children: Vec<usize>,
+        keys: Vec<u32>,
+    ) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let new_page_num = self.pager.get_unused_page_num()?;
+
+        // the middle key moves up into the parent instead of staying in either half
+        let split_at = keys.len() / 2;
+        let separator = keys[split_at];
+        let (left_children, right_children) = children.split_at(split_at + 1);
+        let (left_keys, right_keys) = (&keys[..split_at], &keys[(split_at + 1)..]);
+
+        let node = self.pager.get_page_mut(page_num)?.node_mut();
+        node.set_entries(left_children, left_keys);
+        let parent_page_num = node.get_parent();
+
+        let new_node = self.pager.get_page_mut(new_page_num)?.node_mut();
+        *new_node = Node::new_internal(page_size);
+        new_node.set_entries(right_children, right_keys);
+        new_node.set_parent(parent_page_num);
+
+        for child in left_children {
+            self.pager
+                .get_page_mut(*child)?
+                .node_mut()
+                .set_parent(page_num);
+        }
+        for child in right_children {
+            self.pager
+                .get_page_mut(*child)?
+                .node_mut()
+                .set_parent(new_page_num);
+        }
+
+        self.insert_into_parent(page_num, separator, new_page_num)
+    }
+
+    /// The root always stays on `root_page_num`, so its current content is moved to a new
+    /// page that becomes the left child of the new root.
+    fn create_new_root(&mut self, separator: u32, right_page_num: usize) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let left_page_num = self.pager.get_unused_page_num()?;
+
+        let root = self.pager.get_page_mut(self.root_page_num)?;
+        let mut left_node = Node::from(root.to_vec_mut().clone());
+        left_node.set_root(false);
+        left_node.set_parent(self.root_page_num);
+
+        let root_node = root.node_mut();
+        *root_node = Node::new_internal(page_size);
+        root_node.set_root(true);
+        root_node.set_entries(&[left_page_num, right_page_num], &[separator]);
+
+        let grandchildren = if left_node.is_leaf() {
+            vec![]
+        } else {
+            left_node.get_children()
+        };
+        *self.pager.get_page_mut(left_page_num)?.node_mut() = left_node;
+
+        for child in grandchildren {
+            self.pager
+                .get_page_mut(child)?
+                .node_mut()
+                .set_parent(left_page_num);
+        }
+        self.pager
+            .get_page_mut(right_page_num)?
+            .node_mut()
+            .set_parent(self.root_page_num);
+
+        Ok(())
+    }
+
+    /// Re-serializes `value` into the cell `cursor` points at. The new cell is built before
+    /// the page is touched, so a value that fails to serialize leaves the old row in place.
+    /// It can be larger than the old one, in which case the leaf is split with the new cell
+    /// taking the old one's place. The old cell is only dropped once the new one has a page
+    /// to go to.
+    pub fn update(
+        &mut self,
+        cursor: &Cursor,
+        value: &Row,
+        schema: &Schema,
+    ) -> Result<(), TableError> {
+        let page_size = self.pager.page_size();
+        let node = self.pager.get_page_mut(cursor.page())?.node_mut();
+        let cell = leaf_cell(
+            node.get_cell_key(cursor.cell_num()),
+            value,
+            schema,
+            page_size,
+        )?;
+
+        if node.has_room_to_replace(cursor.cell_num(), &cell) {
+            node.remove_cell(cursor.cell_num());
+            node.insert_cell(cursor.cell_num(), &cell);
+            return Ok(());
+        }
+
+        let mut cells = node.get_cells();
+        cells[cursor.cell_num()] = cell;
+        self.split_leaf(cursor.page(), cells)?;
+
+        Ok(())
+    }
+
+    /// Removes the cell `cursor` points at. A leaf left with too few cells borrows from or
+    /// merges with a sibling, which can cascade up the tree and remove a level at the root.
+    pub fn delete(&mut self, cursor: &Cursor) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let node = self.pager.get_page_mut(cursor.page())?.node_mut();
+        node.remove_cell(cursor.cell_num());
+
+        if node.is_root() || node.get_used_space() >= leaf_node_min_fill(page_size) {
+            return Ok(());
+        }
+
+        self.rebalance(cursor.page())
+    }
+
+    fn rebalance(&mut self, page_num: usize) -> Result<(), PagerError> {
+        let page_size = self.pager.page_size();
+        let node = self.pager.get_page(page_num)?.node();
+        let is_leaf = node.is_leaf();
+        let parent_page_num = node.get_parent();
+
+        let parent_node = self.pager.get_page(parent_page_num)?.node();
+        let mut children = parent_node.get_children();
+        let mut keys = parent_node.get_keys();
+
+        let child_index = children
+            .iter()
+            .position(|child| *child == page_num)
+            .expect("underfull node is missing from its parent");
+
+        // pair the node with its left sibling, or its right one when it is the first child
+        let left_index = child_index.saturating_sub(1);
+        let (left_page_num, right_page_num) = (children[left_index], children[left_index + 1]);
+
+        let merged = if is_leaf {
+            self.rebalance_leaves(left_page_num, right_page_num, &mut keys[left_index])?
+        } else {
+            self.rebalance_internals(left_page_num, right_page_num, &mut keys[left_index])?
+        };
+
+        if merged {
+            // the merged node keeps the right node's upper bound
+            children.remove(left_index + 1);
+            keys.remove(left_index);
+            self.pager.free_page(right_page_num)?;
+        }
+
+        let parent_node = self.pager.get_page_mut(parent_page_num)?.node_mut();
+        parent_node.set_entries(&children, &keys);
+
+        if parent_node.is_root() {
+            if keys.is_empty() {
+                self.collapse_root()?;
             }
+            return Ok(());
+        }
+
+        if keys.len() < internal_node_min_cells(page_size) {
+            self.rebalance(parent_page_num)?;
         }
+
         Ok(())
     }
 
-    pub fn get_root_page_num(&self) -> usize {
-        self.root_page_num
+    /// Merges two adjacent leaves when their cells fit in one, otherwise shares the cells
+    /// between them as evenly as their sizes allow. Returns whether `right_page_num` was
+    /// merged into `left_page_num`.
+    fn rebalance_leaves(
+        &mut self,
+        left_page_num: usize,
+        right_page_num: usize,
+        separator: &mut u32,
+    ) -> Result<bool, PagerError> {
+        let page_size = self.pager.page_size();
+        let right_node = self.pager.get_page(right_page_num)?.node();
+        let right_cells = right_node.get_cells();
+        let right_next_leaf = right_node.get_next_leaf();
+
+        let left_node = self.pager.get_page_mut(left_page_num)?.node_mut();
+        let mut cells = left_node.get_cells();
+        cells.extend(right_cells);
+
+        if leaf_cells_size(&cells) <= leaf_node_capacity(page_size) {
+            left_node.set_cells(&cells);
+            left_node.set_next_leaf(right_next_leaf);
+            return Ok(true);
+        }
+
+        let (left_cells, right_cells) = cells.split_at(leaf_split_point(&cells, page_size));
+        left_node.set_cells(left_cells);
+        *separator = left_node.get_cell_key(left_cells.len() - 1);
+        self.pager
+            .get_page_mut(right_page_num)?
+            .node_mut()
+            .set_cells(right_cells);
+
+        Ok(false)
+    }
+
+    /// Same as `rebalance_leaves` for internal nodes, where the separator from the parent
+    /// is pulled down between the two nodes' keys.
+    fn rebalance_internals(
+        &mut self,
+        left_page_num: usize,
+        right_page_num: usize,
+        separator: &mut u32,
+    ) -> Result<bool, PagerError> {
+        let page_size = self.pager.page_size();
+        let right_node = self.pager.get_page(right_page_num)?.node();
+        let right_children = right_node.get_children();
+        let right_keys = right_node.get_keys();
+
+        let left_node = self.pager.get_page(left_page_num)?.node();
+        let mut children = left_node.get_children();
+        let mut keys = left_node.get_keys();
+        children.extend(right_children);
+        keys.push(*separator);
+        keys.extend(right_keys);
+
+        if keys.len() <= internal_node_max_cells(page_size) {
+            self.pager
+                .get_page_mut(left_page_num)?
+                .node_mut()
+                .set_entries(&children, &keys);
+            for child in children {
+                self.pager
+                    .get_page_mut(child)?
+                    .node_mut()
+                    .set_parent(left_page_num);
+            }
+            return Ok(true);
+        }
+
+        let split_at = keys.len() / 2;
+        *separator = keys[split_at];
+        let (left_children, right_children) = children.split_at(split_at + 1);
+
+        self.pager
+            .get_page_mut(left_page_num)?
+            .node_mut()
+            .set_entries(left_children, &keys[..split_at]);
+        self.pager
+            .get_page_mut(right_page_num)?
+            .node_mut()
+            .set_entries(right_children, &keys[(split_at + 1)..]);
+
+        for child in left_children {
+            self.pager
+                .get_page_mut(*child)?
+                .node_mut()
+                .set_parent(left_page_num);
+        }
+        for child in right_children {
+            self.pager
+                .get_page_mut(*child)?
+                .node_mut()
+                .set_parent(right_page_num);
+        }
+
+        Ok(false)
+    }
+
+    /// Once the root is down to a single child, that child's content moves up into the root
+    /// page and the tree loses a level.
+    fn collapse_root(&mut self) -> Result<(), PagerError> {
+        let root_node = self.pager.get_page(self.root_page_num)?.node();
+        let child_page_num = root_node.get_child(0);
+
+        let child = self.pager.get_page(child_page_num)?;
+        let mut node = Node::from(child.as_slice().to_vec());
+        node.set_root(true);
+
+        let grandchildren = if node.is_leaf() {
+            vec![]
+        } else {
+            node.get_children()
+        };
+        *self.pager.get_page_mut(self.root_page_num)?.node_mut() = node;
+
+        for grandchild in grandchildren {
+            self.pager
+                .get_page_mut(grandchild)?
+                .node_mut()
+                .set_parent(self.root_page_num);
+        }
+        self.pager.free_page(child_page_num)
     }
 }
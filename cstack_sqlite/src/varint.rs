@@ -0,0 +1,53 @@
+//! Variable-length integers, encoded the way SQLite does it: big-endian groups of 7 bits with
+//! the high bit of every byte but the last one set. A ninth byte, when one is needed, holds a
+//! full 8 bits, so every `u64` takes at most 9 bytes and small values only one.
+
+pub const VARINT_MAX_SIZE: usize = 9;
+
+/// Number of bytes `value` takes up once encoded.
+pub fn len(value: u64) -> usize {
+    // the first 8 bytes carry 56 bits between them
+    if value >> 56 != 0 {
+        return VARINT_MAX_SIZE;
+    }
+
+    let bits = (u64::BITS - value.leading_zeros()) as usize;
+    bits.div_ceil(7).max(1)
+}
+
+/// Appends the encoding of `value` to `dest`.
+pub fn write(dest: &mut Vec<u8>, value: u64) {
+    let len = len(value);
+
+    if len == VARINT_MAX_SIZE {
+        for group in (0..8).rev() {
+            dest.push(((value >> (8 + 7 * group)) & 0x7f) as u8 | 0x80);
+        }
+        dest.push(value as u8);
+        return;
+    }
+
+    for group in (0..len).rev() {
+        let byte = ((value >> (7 * group)) & 0x7f) as u8;
+        dest.push(if group == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+/// Decodes the varint at the start of `src`, returning it with the number of bytes it took
+/// up, or `None` when `src` ends before it does.
+pub fn read(src: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0;
+
+    for (i, byte) in src.iter().take(VARINT_MAX_SIZE).enumerate() {
+        if i == VARINT_MAX_SIZE - 1 {
+            return Some(((value << 8) | *byte as u64, VARINT_MAX_SIZE));
+        }
+
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
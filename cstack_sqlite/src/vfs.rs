@@ -0,0 +1,313 @@
+//! The pager, rollback journal and WAL reach storage only through these traits, so a
+//! database can live somewhere other than the local file system.
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Result},
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use libc::{c_int, c_short};
+
+/// Locks a connection takes on the database file, from the weakest to the strongest. Each
+/// one includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    /// Any number of connections can hold a shared lock at once, to read
+    Shared,
+    /// Only one connection can hold a reserved lock, to write pages it has not committed
+    /// yet. Connections holding a shared lock can keep reading meanwhile
+    Reserved,
+    /// Only one connection can hold an exclusive lock, and no one else a shared one, to
+    /// write to the database file
+    Exclusive,
+}
+
+pub trait Vfs {
+    /// Opens `path` for reading and writing, creating an empty file when it is missing.
+    fn open(&self, path: &Path) -> Result<Box<dyn VfsFile>>;
+    fn exists(&self, path: &Path) -> Result<bool>;
+    fn delete(&self, path: &Path) -> Result<()>;
+}
+
+pub trait VfsFile {
+    /// Fills `buf` from `offset`, failing if the file ends first.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()>;
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<()>;
+    /// Returns once everything written so far is durable.
+    fn sync(&self) -> Result<()>;
+    /// Sets the file length, cutting it off or extending it with zeros.
+    fn truncate(&self, len: u64) -> Result<()>;
+    fn size(&self) -> Result<u64>;
+    /// Tries to raise the lock held on the file to `mode` without waiting. Returns false
+    /// when another connection holds a conflicting lock, in which case the lock may have
+    /// been raised partway.
+    fn lock(&self, mode: LockMode) -> Result<bool>;
+    /// Lowers the lock held on the file to `mode`, or releases it with `None`.
+    fn unlock(&self, mode: Option<LockMode>) -> Result<()>;
+    /// Returns whether another connection holds a reserved or exclusive lock on the file.
+    fn is_reserved(&self) -> Result<bool>;
+}
+
+/// Files on the local file system.
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn VfsFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(Box::new(OsFile {
+            file,
+            lock: Cell::new(None),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        match fs::metadata(path) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+// Locks are taken on single bytes at the 1 GiB offset SQLite uses, so the levels can be told
+// apart. A database that grows past 1 GiB has pages over those bytes, which is harmless:
+// the locks are advisory, so they keep no one from reading or writing the bytes, and only
+// other lock calls ever look at them. A connection that wants an exclusive lock holds the
+// pending byte while it waits for readers to leave, which keeps new ones from coming in.
+const PENDING_BYTE: u64 = 0x4000_0000;
+const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+const SHARED_BYTE: u64 = PENDING_BYTE + 2;
+
+// open file description locks belong to the open file like `flock` ones do, so two
+// connections in one process exclude each other. Elsewhere only the POSIX locks of the
+// whole process are available
+#[cfg(target_os = "linux")]
+const F_SETLK: c_int = libc::F_OFD_SETLK;
+#[cfg(target_os = "linux")]
+const F_GETLK: c_int = libc::F_OFD_GETLK;
+#[cfg(not(target_os = "linux"))]
+const F_SETLK: c_int = libc::F_SETLK;
+#[cfg(not(target_os = "linux"))]
+const F_GETLK: c_int = libc::F_GETLK;
+
+pub struct OsFile {
+    file: File,
+    // lock held on the file, none when it is unlocked
+    lock: Cell<Option<LockMode>>,
+}
+
+impl OsFile {
+    fn byte_range_lock(lock_type: c_int, start: u64, len: u64) -> libc::flock {
+        // SAFETY: `flock` is plain data, for which all zeroes is a valid value
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        lock.l_type = lock_type as c_short;
+        lock.l_whence = libc::SEEK_SET as c_short;
+        lock.l_start = start as libc::off_t;
+        lock.l_len = len as libc::off_t;
+        lock
+    }
+
+    /// Sets a lock of `lock_type` on `len` bytes from `start`, or clears it with `F_UNLCK`.
+    /// Returns false when another connection holds a conflicting lock.
+    fn set_lock(&self, lock_type: c_int, start: u64, len: u64) -> Result<bool> {
+        let lock = Self::byte_range_lock(lock_type, start, len);
+
+        // SAFETY: the descriptor is open for as long as `self.file` is, and `lock` outlives
+        // the call
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), F_SETLK, &lock) } != -1 {
+            return Ok(true);
+        }
+
+        let e = Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EAGAIN | libc::EACCES) => Ok(false),
+            _ => Err(e),
+        }
+    }
+}
+
+impl VfsFile for OsFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        self.file.write_all_at(buf, offset)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.file.sync_all()
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn lock(&self, mode: LockMode) -> Result<bool> {
+        if self.lock.get().is_none() {
+            // a reader cannot come in while a writer is waiting on the pending byte
+            if !self.set_lock(libc::F_RDLCK, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            let shared = self.set_lock(libc::F_RDLCK, SHARED_BYTE, 1);
+            self.set_lock(libc::F_UNLCK, PENDING_BYTE, 1)?;
+            if !shared? {
+                return Ok(false);
+            }
+            self.lock.set(Some(LockMode::Shared));
+        }
+
+        if mode >= LockMode::Reserved && self.lock.get() < Some(LockMode::Reserved) {
+            if !self.set_lock(libc::F_WRLCK, RESERVED_BYTE, 1)? {
+                return Ok(false);
+            }
+            self.lock.set(Some(LockMode::Reserved));
+        }
+
+        if mode == LockMode::Exclusive && self.lock.get() < Some(LockMode::Exclusive) {
+            // the pending byte stays held when readers are still in, until the lock is
+            // lowered
+            if !self.set_lock(libc::F_WRLCK, PENDING_BYTE, 1)?
+                || !self.set_lock(libc::F_WRLCK, SHARED_BYTE, 1)?
+            {
+                return Ok(false);
+            }
+            self.lock.set(Some(LockMode::Exclusive));
+        }
+
+        Ok(true)
+    }
+
+    fn unlock(&self, mode: Option<LockMode>) -> Result<()> {
+        let Some(mode) = mode else {
+            self.set_lock(libc::F_UNLCK, PENDING_BYTE, 3)?;
+            self.lock.set(None);
+            return Ok(());
+        };
+
+        if mode < LockMode::Exclusive && self.lock.get() == Some(LockMode::Exclusive) {
+            self.set_lock(libc::F_RDLCK, SHARED_BYTE, 1)?;
+        }
+        self.set_lock(libc::F_UNLCK, PENDING_BYTE, 1)?;
+        if mode < LockMode::Reserved {
+            self.set_lock(libc::F_UNLCK, RESERVED_BYTE, 1)?;
+        }
+        self.lock.set(self.lock.get().min(Some(mode)));
+        Ok(())
+    }
+
+    fn is_reserved(&self) -> Result<bool> {
+        let mut lock = Self::byte_range_lock(libc::F_WRLCK, RESERVED_BYTE, 1);
+
+        // SAFETY: the descriptor is open for as long as `self.file` is, and `lock` outlives
+        // the call
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), F_GETLK, &mut lock) } == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(lock.l_type != libc::F_UNLCK as c_short)
+    }
+}
+
+/// Files held in memory, gone once the last handle to them is dropped. Only one connection
+/// can use them, so locks are always granted.
+#[derive(Default)]
+pub struct MemoryVfs {
+    files: RefCell<HashMap<PathBuf, Rc<RefCell<Vec<u8>>>>>,
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn VfsFile>> {
+        let data = self
+            .files
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+
+        Ok(Box::new(MemoryFile(data)))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        Ok(self.files.borrow().contains_key(path))
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        match self.files.borrow_mut().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Error::from(ErrorKind::NotFound)),
+        }
+    }
+}
+
+pub struct MemoryFile(Rc<RefCell<Vec<u8>>>);
+
+impl VfsFile for MemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let data = self.0.borrow();
+        let start = offset as usize;
+
+        match data.get(start..start + buf.len()) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(Error::from(ErrorKind::UnexpectedEof)),
+        }
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        let mut data = self.0.borrow_mut();
+        let start = offset as usize;
+        let end = start + buf.len();
+
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.0.borrow_mut().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.0.borrow().len() as u64)
+    }
+
+    fn lock(&self, _mode: LockMode) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn unlock(&self, _mode: Option<LockMode>) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_reserved(&self) -> Result<bool> {
+        Ok(false)
+    }
+}
@@ -0,0 +1,352 @@
+//! Write-ahead log. In WAL mode the database file is left alone while changes are committed:
+//! every changed page is appended to `<db>-wal` as a frame, and the last frame of a commit
+//! records the database size, which marks everything before it as committed. Reads check
+//! the WAL index for the newest frame of a page before falling back to the database file,
+//! and a checkpoint copies the newest frames back into the file and empties the log.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    checksum::crc32,
+    page::{read_u32, write_u32},
+    pager::{is_valid_page_size, PagerError, Synchronous},
+    vfs::{Vfs, VfsFile},
+};
+
+pub const WAL_MAGIC: &[u8] = b"csqwal\0\0";
+// a commit that leaves at least this many frames in the log is followed by a checkpoint
+pub const WAL_AUTOCHECKPOINT_FRAMES: usize = 1000;
+
+// WAL Header Layout
+pub const WAL_MAGIC_SIZE: usize = WAL_MAGIC.len();
+pub const WAL_MAGIC_OFFSET: usize = 0;
+pub const WAL_PAGE_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+pub const WAL_PAGE_SIZE_OFFSET: usize = WAL_MAGIC_OFFSET + WAL_MAGIC_SIZE;
+// bumped every time the log is emptied, so other connections can tell it was checkpointed
+pub const WAL_CHECKPOINT_SEQ_SIZE: usize = std::mem::size_of::<u32>();
+pub const WAL_CHECKPOINT_SEQ_OFFSET: usize = WAL_PAGE_SIZE_OFFSET + WAL_PAGE_SIZE_SIZE;
+pub const WAL_HEADER_CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+pub const WAL_HEADER_CHECKSUM_OFFSET: usize = WAL_CHECKPOINT_SEQ_OFFSET + WAL_CHECKPOINT_SEQ_SIZE;
+pub const WAL_HEADER_SIZE: usize =
+    WAL_MAGIC_SIZE + WAL_PAGE_SIZE_SIZE + WAL_CHECKPOINT_SEQ_SIZE + WAL_HEADER_CHECKSUM_SIZE;
+
+// WAL Frame Layout
+// the commit field holds the database size in pages on the last frame of a commit, 0 otherwise
+pub const FRAME_PAGE_NUM_SIZE: usize = std::mem::size_of::<u32>();
+pub const FRAME_PAGE_NUM_OFFSET: usize = 0;
+pub const FRAME_COMMIT_SIZE: usize = std::mem::size_of::<u32>();
+pub const FRAME_COMMIT_OFFSET: usize = FRAME_PAGE_NUM_OFFSET + FRAME_PAGE_NUM_SIZE;
+// the image that follows is as long as the page size in the header, then comes the checksum
+pub const FRAME_IMAGE_OFFSET: usize = FRAME_COMMIT_OFFSET + FRAME_COMMIT_SIZE;
+pub const FRAME_CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
+pub const fn frame_size(page_size: usize) -> usize {
+    FRAME_IMAGE_OFFSET + page_size + FRAME_CHECKSUM_SIZE
+}
+
+pub fn wal_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push("-wal");
+    PathBuf::from(path)
+}
+
+/// Returns the page size and checkpoint sequence in the log's header, or `None` when the
+/// header is not valid.
+fn read_header(file: &dyn VfsFile) -> Option<(usize, u32)> {
+    let mut header = [0; WAL_HEADER_SIZE];
+    let header_valid = file.read_at(&mut header, 0).is_ok()
+        && &header[..WAL_MAGIC_SIZE] == WAL_MAGIC
+        && read_u32(&header, WAL_HEADER_CHECKSUM_OFFSET)
+            == crc32(&header[..WAL_HEADER_CHECKSUM_OFFSET]);
+    let page_size = read_u32(&header, WAL_PAGE_SIZE_OFFSET) as usize;
+    let checkpoint_seq = read_u32(&header, WAL_CHECKPOINT_SEQ_OFFSET);
+
+    (header_valid && is_valid_page_size(page_size)).then_some((page_size, checkpoint_seq))
+}
+
+pub struct Wal {
+    file: Box<dyn VfsFile>,
+    page_size: usize,
+    synchronous: Synchronous,
+    checkpoint_seq: u32,
+    len: usize,
+    // end of the last commit frame, where the frames of the commit in progress start
+    committed_len: usize,
+    // offset of the newest committed frame of each page
+    index: HashMap<usize, usize>,
+    // offset of the newest frame of each page written by the commit in progress
+    uncommitted: HashMap<usize, usize>,
+    // database size in pages as of the last commit in the log
+    page_count: Option<usize>,
+}
+
+impl Wal {
+    /// Opens the log next to the database, creating it when missing, and rebuilds the index
+    /// from its frames. A log written with a page size other than `page_size` is emptied.
+    pub fn open(
+        vfs: &dyn Vfs,
+        db_path: &Path,
+        page_size: usize,
+        synchronous: Synchronous,
+    ) -> Result<Self, PagerError> {
+        let file = vfs
+            .open(&wal_path(db_path))
+            .map_err(PagerError::JournalFailed)?;
+
+        let mut wal = Self {
+            file,
+            page_size,
+            synchronous,
+            checkpoint_seq: 0,
+            len: WAL_HEADER_SIZE,
+            committed_len: WAL_HEADER_SIZE,
+            index: HashMap::new(),
+            uncommitted: HashMap::new(),
+            page_count: None,
+        };
+
+        wal.load()?;
+        Ok(wal)
+    }
+
+    /// Rebuilds the index from the frames in the log.
+    fn load(&mut self) -> Result<(), PagerError> {
+        self.index.clear();
+        self.uncommitted.clear();
+        self.page_count = None;
+        self.len = WAL_HEADER_SIZE;
+        self.committed_len = WAL_HEADER_SIZE;
+
+        match read_header(self.file.as_ref()) {
+            Some((page_size, checkpoint_seq)) if page_size == self.page_size => {
+                self.checkpoint_seq = checkpoint_seq;
+            }
+            _ => return self.reset(),
+        }
+
+        self.read_commits();
+        Ok(())
+    }
+
+    /// Adds the commits appended to the log after the last one in the index. Frames after
+    /// the last commit belong to a commit still in progress or to one that never finished,
+    /// so they are left out, and the next commit writes over them. Returns whether any
+    /// commit was added.
+    fn read_commits(&mut self) -> bool {
+        let checksum_offset = FRAME_IMAGE_OFFSET + self.page_size;
+        let mut frame = vec![0; frame_size(self.page_size)];
+        let mut offset = self.committed_len;
+        let mut pending = HashMap::new();
+        let mut added = false;
+
+        while self.file.read_at(&mut frame, offset as u64).is_ok() {
+            if read_u32(&frame, checksum_offset) != crc32(&frame[..checksum_offset]) {
+                break;
+            }
+
+            let page_num = read_u32(&frame, FRAME_PAGE_NUM_OFFSET) as usize;
+            pending.insert(page_num, offset);
+            offset += frame.len();
+
+            let commit = read_u32(&frame, FRAME_COMMIT_OFFSET) as usize;
+            if commit != 0 {
+                self.index.extend(pending.drain());
+                self.page_count = Some(commit);
+                self.committed_len = offset;
+                added = true;
+            }
+        }
+
+        self.len = self.committed_len;
+        added
+    }
+
+    /// Picks up the commits and checkpoints other connections made since the index was
+    /// built. Returns whether the log changed.
+    pub fn refresh(&mut self) -> Result<bool, PagerError> {
+        let checkpoint_seq = read_header(self.file.as_ref()).map(|(_, seq)| seq);
+
+        if checkpoint_seq != Some(self.checkpoint_seq) {
+            self.load()?;
+            return Ok(true);
+        }
+
+        Ok(self.read_commits())
+    }
+
+    /// Copies the committed frames of a log left behind by a WAL mode connection into the
+    /// database file and deletes the log, for connections that do not use one.
+    pub fn recover(
+        vfs: &dyn Vfs,
+        db_path: &Path,
+        db_file: &dyn VfsFile,
+        page_size: usize,
+    ) -> Result<(), PagerError> {
+        let path = wal_path(db_path);
+        if !vfs.exists(&path).map_err(PagerError::JournalFailed)? {
+            return Ok(());
+        }
+
+        let mut wal = Self::open(vfs, db_path, page_size, Synchronous::Full)?;
+        wal.checkpoint(db_file)?;
+        vfs.delete(&path).map_err(PagerError::JournalFailed)
+    }
+
+    /// Returns the page size of the log next to the database, or `None` when there is no
+    /// valid log.
+    pub fn read_page_size(vfs: &dyn Vfs, db_path: &Path) -> Result<Option<usize>, PagerError> {
+        let path = wal_path(db_path);
+        if !vfs.exists(&path).map_err(PagerError::JournalFailed)? {
+            return Ok(None);
+        }
+
+        let file = vfs.open(&path).map_err(PagerError::JournalFailed)?;
+        Ok(read_header(file.as_ref()).map(|(page_size, _)| page_size))
+    }
+
+    /// Empties the log, leaving only a fresh header.
+    fn reset(&mut self) -> Result<(), PagerError> {
+        self.checkpoint_seq = self.checkpoint_seq.wrapping_add(1);
+
+        let mut header = [0; WAL_HEADER_SIZE];
+        header[..WAL_MAGIC_SIZE].copy_from_slice(WAL_MAGIC);
+        write_u32(&mut header, WAL_PAGE_SIZE_OFFSET, self.page_size as u32);
+        write_u32(&mut header, WAL_CHECKPOINT_SEQ_OFFSET, self.checkpoint_seq);
+        let checksum = crc32(&header[..WAL_HEADER_CHECKSUM_OFFSET]);
+        write_u32(&mut header, WAL_HEADER_CHECKSUM_OFFSET, checksum);
+
+        self.file
+            .truncate(0)
+            .and_then(|_| self.file.write_at(&header, 0))
+            .map_err(PagerError::JournalFailed)?;
+        if self.synchronous > Synchronous::Off {
+            self.file.sync().map_err(PagerError::JournalFailed)?;
+        }
+
+        self.len = WAL_HEADER_SIZE;
+        self.committed_len = WAL_HEADER_SIZE;
+        self.index.clear();
+        self.uncommitted.clear();
+        self.page_count = None;
+        Ok(())
+    }
+
+    pub fn page_count(&self) -> Option<usize> {
+        self.page_count
+    }
+
+    pub fn frame_count(&self) -> usize {
+        (self.len - WAL_HEADER_SIZE) / frame_size(self.page_size)
+    }
+
+    pub fn has_uncommitted(&self) -> bool {
+        !self.uncommitted.is_empty()
+    }
+
+    pub fn contains(&self, page_num: usize) -> bool {
+        self.uncommitted.contains_key(&page_num) || self.index.contains_key(&page_num)
+    }
+
+    /// Reads the newest image of `page_num` in the log into `raw`. The commit in progress
+    /// sees its own frames before the committed ones.
+    pub fn read_page(&self, page_num: usize, raw: &mut [u8]) -> Result<(), PagerError> {
+        let offset = self
+            .uncommitted
+            .get(&page_num)
+            .or(self.index.get(&page_num))
+            .expect("page is not in the wal");
+
+        self.file
+            .read_at(
+                &mut raw[..self.page_size],
+                (offset + FRAME_IMAGE_OFFSET) as u64,
+            )
+            .map_err(PagerError::ReadFailed)
+    }
+
+    /// Appends a frame holding `image` for `page_num`. Passing the database size in pages
+    /// as `commit` makes this the last frame of the commit.
+    pub fn write_frame(
+        &mut self,
+        page_num: usize,
+        image: &[u8],
+        commit: Option<usize>,
+    ) -> Result<(), PagerError> {
+        let checksum_offset = FRAME_IMAGE_OFFSET + self.page_size;
+        let mut frame = vec![0; frame_size(self.page_size)];
+        write_u32(&mut frame, FRAME_PAGE_NUM_OFFSET, page_num as u32);
+        write_u32(&mut frame, FRAME_COMMIT_OFFSET, commit.unwrap_or(0) as u32);
+        frame[FRAME_IMAGE_OFFSET..checksum_offset].copy_from_slice(&image[..self.page_size]);
+        let checksum = crc32(&frame[..checksum_offset]);
+        write_u32(&mut frame, checksum_offset, checksum);
+
+        self.file
+            .write_at(&frame, self.len as u64)
+            .map_err(PagerError::FlushFailed)?;
+        self.uncommitted.insert(page_num, self.len);
+        self.len += frame.len();
+
+        if commit.is_some() {
+            if self.synchronous == Synchronous::Full {
+                self.file.sync().map_err(PagerError::FlushFailed)?;
+            }
+            self.index.extend(self.uncommitted.drain());
+            self.page_count = commit;
+            self.committed_len = self.len;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the frames of the commit in progress.
+    pub fn rollback(&mut self) -> Result<(), PagerError> {
+        self.file
+            .truncate(self.committed_len as u64)
+            .map_err(PagerError::JournalFailed)?;
+
+        self.len = self.committed_len;
+        self.uncommitted.clear();
+        Ok(())
+    }
+
+    /// Copies the newest committed image of every page in the log into the database file,
+    /// syncs it and empties the log. Skipped while a commit is in progress, since emptying
+    /// the log would lose the frames it already spilled. When commits are not synced, the log
+    /// is synced first so the file never holds changes the log could still lose.
+    pub fn checkpoint(&mut self, db_file: &dyn VfsFile) -> Result<(), PagerError> {
+        let Some(page_count) = self.page_count else {
+            return Ok(());
+        };
+        if self.has_uncommitted() {
+            return Ok(());
+        }
+
+        if self.synchronous == Synchronous::Normal {
+            self.file.sync().map_err(PagerError::FlushFailed)?;
+        }
+
+        let mut image = vec![0; self.page_size];
+        let mut pages: Vec<_> = self.index.iter().map(|(p, o)| (*p, *o)).collect();
+        pages.sort_unstable();
+
+        for (page_num, offset) in pages {
+            self.file
+                .read_at(&mut image, (offset + FRAME_IMAGE_OFFSET) as u64)
+                .map_err(PagerError::ReadFailed)?;
+            db_file
+                .write_at(&image, (page_num * self.page_size) as u64)
+                .map_err(PagerError::FlushFailed)?;
+        }
+
+        db_file
+            .truncate((page_count * self.page_size) as u64)
+            .map_err(PagerError::FlushFailed)?;
+        if self.synchronous > Synchronous::Off {
+            db_file.sync().map_err(PagerError::FlushFailed)?;
+        }
+
+        self.reset()
+    }
+}
@@ -1,4 +1,18 @@
-use utils::{gen_random_filename, result_match, run_script_exec, run_script_exec_with_defaults};
+use cstack_sqlite::{
+    db::{Database, DatabaseError, HandleDBQueryStatusCode, MEMORY_DB_FILENAME},
+    pager::{JournalMode, PagerConfig},
+};
+use std::{
+    fs::{metadata, read, remove_file, write},
+    io::{self, BufRead, BufReader, Write},
+    thread,
+    time::Duration,
+};
+use utils::{
+    gen_random_filename, result_match, run_script_exec, run_script_exec_with_args,
+    run_script_exec_with_defaults, run_script_exec_with_small_pages, run_script_in_memory,
+    spawn_session,
+};
 mod utils;
 
 // TODO: dry out test cases with a macro
@@ -20,15 +34,1453 @@ fn inserts_and_retrieves_row() {
 }
 
 #[test]
-fn print_error_message_when_table_is_full() {
+fn inserts_thousands_of_rows() {
     let mut scripts = vec![];
-    for i in 0..1401 {
+    for i in 0..4000 {
         scripts.push(format!("insert {i} user{i} person{i}@example.com"));
     }
+    scripts.push("select where id >= 3998".to_owned());
+    scripts.push(".btree".to_owned());
     scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    assert_eq!(results[3999], "csquarelite> Executed.");
+    result_match(
+        results[4000..].to_vec(),
+        vec![
+            "csquarelite> Row { id: 3998, username: \"user3998\", email: \"person3998@example.com\" }",
+            "Row { id: 3999, username: \"user3999\", email: \"person3999@example.com\" }",
+            "Executed.",
+            "csquarelite> Tree:",
+            "- internal (size 19)",
+            "  - internal (size 31)",
+            "    - leaf (size 7)",
+        ],
+    );
+}
+
+#[test]
+fn splits_a_full_leaf_into_a_new_root() {
+    let mut scripts = vec![];
+    for i in 1..15 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push(".btree".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- internal (size 1)".to_owned(),
+    ];
+    expected.push("  - leaf (size 7)".to_owned());
+    expected.extend((1..8).map(|i| format!("    - {i}")));
+    expected.push("  - key 7".to_owned());
+    expected.push("  - leaf (size 7)".to_owned());
+    expected.extend((8..15).map(|i| format!("    - {i}")));
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[14..].to_vec(), expected);
+}
+
+#[test]
+fn fits_as_many_rows_in_a_leaf_as_their_size_allows() {
+    let mut scripts = vec![];
+    for i in 1..31 {
+        scripts.push(format!("insert {i} u{i} e"));
+    }
+    scripts.push(".btree".to_owned());
+    // a row can take up at most half a page, so a full leaf can always be split in two
+    scripts.push(format!("insert 31 {} {}", "a".repeat(32), "b".repeat(255)));
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- leaf (size 30)".to_owned(),
+    ];
+    expected.extend((1..31).map(|i| format!("  - {i}")));
+    expected.push("csquarelite> Row too long: 299 bytes, a page holds at most 242.".to_owned());
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[30..].to_vec(), expected);
+}
+
+#[test]
+fn keeps_a_multi_level_tree_after_closing_connection() {
+    let db_filename = gen_random_filename();
+    let mut scripts = vec![];
+    for i in 1..30 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push(".exit".to_owned());
+    run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--page-size", "512"],
+    );
+
+    let scripts = vec![".btree", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename), true);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- internal (size 3)".to_owned(),
+    ];
+    for (first, last) in [(1, 7), (8, 14), (15, 20)] {
+        expected.push(format!("  - leaf (size {})", last - first + 1));
+        expected.extend((first..=last).map(|i| format!("    - {i}")));
+        expected.push(format!("  - key {last}"));
+    }
+    expected.push("  - leaf (size 9)".to_owned());
+    expected.extend((21..30).map(|i| format!("    - {i}")));
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results, expected);
+}
+
+#[test]
+fn finds_a_row_by_id_across_leaves() {
+    let mut scripts = vec![];
+    for i in 1..30 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("select where id = 17".to_owned());
+    scripts.push("select where id = 100".to_owned());
+    scripts.push("select where name = 3".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    result_match(
+        results[29..].to_vec(),
+        vec![
+            "csquarelite> Row { id: 17, username: \"user17\", email: \"person17@example.com\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Syntax Error: expected 'where id <op> <id>'",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn keeps_rows_sorted_by_id() {
+    let mut scripts = vec![];
+    for i in (1..21).rev() {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push(".btree".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- internal (size 2)".to_owned(),
+    ];
+    for (first, last) in [(1, 7), (8, 14)] {
+        expected.push("  - leaf (size 7)".to_owned());
+        expected.extend((first..=last).map(|i| format!("    - {i}")));
+        expected.push(format!("  - key {last}"));
+    }
+    expected.push("  - leaf (size 6)".to_owned());
+    expected.extend((15..21).map(|i| format!("    - {i}")));
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[20..].to_vec(), expected);
+}
+
+#[test]
+fn selects_rows_from_every_leaf() {
+    let mut scripts = vec![];
+    for i in (1..31).rev() {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("select".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected: Vec<String> = (1..31)
+        .map(|i| {
+            format!("Row {{ id: {i}, username: \"user{i}\", email: \"person{i}@example.com\" }}")
+        })
+        .collect();
+    expected[0] = format!("csquarelite> {}", expected[0]);
+    expected.push("Executed.".to_owned());
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[30..].to_vec(), expected);
+}
+
+#[test]
+fn merges_underfull_leaves_after_delete() {
+    let mut scripts = vec![];
+    for i in 1..15 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("delete where id = 1".to_owned());
+    scripts.push("delete where id = 2".to_owned());
+    scripts.push("delete where id = 99".to_owned());
+    scripts.push(".btree".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Executed.".to_owned(),
+        "csquarelite> Executed.".to_owned(),
+        "csquarelite> Executed.".to_owned(),
+        "csquarelite> Tree:".to_owned(),
+        "- leaf (size 12)".to_owned(),
+    ];
+    expected.extend((3..15).map(|i| format!("  - {i}")));
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[14..].to_vec(), expected);
+}
+
+#[test]
+fn borrows_from_a_sibling_after_delete() {
+    let mut scripts = vec![];
+    // the left leaf keeps 1 to 7 after the first split, the right one fills up with the rest
+    for i in 1..20 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("delete where id = 1".to_owned());
+    scripts.push(".btree".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- internal (size 1)".to_owned(),
+        "  - leaf (size 9)".to_owned(),
+    ];
+    expected.extend((2..11).map(|i| format!("    - {i}")));
+    expected.push("  - key 10".to_owned());
+    expected.push("  - leaf (size 9)".to_owned());
+    expected.extend((11..20).map(|i| format!("    - {i}")));
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[20..].to_vec(), expected);
+}
+
+#[test]
+fn reuses_space_freed_inside_a_leaf() {
+    let mut scripts = vec![];
+    for i in 1..14 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    for id in [3, 4, 8] {
+        scripts.push(format!("delete where id = {id}"));
+    }
+    // the first row goes where 3 and 4 were, the second is larger than any free space left
+    // between the cells, so they are moved together to make room for it
+    scripts.push("insert 4 user4 person4@example.com".to_owned());
+    scripts.push(format!("insert 3 user3 {}", "e".repeat(60)));
+    scripts.push(".btree".to_owned());
+    scripts.push("select where id >= 3 and id <= 4".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Tree:".to_owned(),
+        "- leaf (size 12)".to_owned(),
+    ];
+    expected.extend((1..14).filter(|i| *i != 8).map(|i| format!("  - {i}")));
+    expected.push(format!(
+        "csquarelite> Row {{ id: 3, username: \"user3\", email: \"{}\" }}",
+        "e".repeat(60)
+    ));
+    expected.push("Row { id: 4, username: \"user4\", email: \"person4@example.com\" }".to_owned());
+    expected.push("Executed.".to_owned());
+    expected.push("csquarelite> ".to_owned());
+
+    result_match(results[18..].to_vec(), expected);
+}
+
+#[test]
+fn reuses_pages_freed_by_delete() {
+    let db_filename = gen_random_filename();
+    let mut scripts = vec![];
+    for i in 0..1400 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    for i in 0..1400 {
+        scripts.push(format!("delete where id = {i}"));
+    }
+    scripts.push(".exit".to_owned());
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    let file_len = metadata(&db_filename).unwrap().len();
+
+    // the freelist survives closing the database, so reinserting takes pages from it
+    let mut scripts = vec![];
+    for i in 0..1400 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push(".exit".to_owned());
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    assert_eq!(metadata(&db_filename).unwrap().len(), file_len);
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn updates_a_row_in_place() {
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "insert 2 user2 person2@example.com",
+        "update set username = admin, email = admin@example.com where id = 2",
+        "update set email = nobody@example.com where id = 3",
+        "select",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Row { id: 2, username: \"admin\", email: \"admin@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn keeps_the_old_row_when_an_update_fails() {
+    let username = "a".repeat(33);
+    let scripts = vec![
+        "insert 1 user1 person1@example.com".to_string(),
+        format!("update set email = new@example.com, username = {username} where id = 1"),
+        "update set id = 2 where id = 1".to_string(),
+        "update set age = 2 where id = 1".to_string(),
+        "select".to_string(),
+        ".exit".to_string(),
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> String value for 'username' too long.",
+            "csquarelite> Validation Error: Column 'id' cannot be updated",
+            "csquarelite> Validation Error: Unknown column 'age'",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn splits_a_leaf_when_an_update_grows_a_row() {
+    let mut scripts = vec![];
+    for i in 1..=10 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    let email = "e".repeat(200);
+    scripts.push(format!("update set email = {email} where id = 5"));
+    scripts.push(".btree".to_owned());
+    scripts.push("select where id >= 4 and id <= 6".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_small_pages(scripts);
+
+    let mut expected = vec![
+        "csquarelite> Executed.".to_owned(),
+        "csquarelite> Tree:".to_owned(),
+        "- internal (size 1)".to_owned(),
+    ];
+    expected.push("  - leaf (size 5)".to_owned());
+    expected.extend((1..6).map(|i| format!("    - {i}")));
+    expected.push("  - key 5".to_owned());
+    expected.push("  - leaf (size 5)".to_owned());
+    expected.extend((6..11).map(|i| format!("    - {i}")));
+    expected.push(
+        "csquarelite> Row { id: 4, username: \"user4\", email: \"person4@example.com\" }"
+            .to_owned(),
+    );
+    expected.push(format!(
+        "Row {{ id: 5, username: \"user5\", email: \"{email}\" }}"
+    ));
+    expected.push("Row { id: 6, username: \"user6\", email: \"person6@example.com\" }".to_owned());
+
+    result_match(results[10..].to_vec(), expected);
+}
+
+#[test]
+fn selects_rows_in_an_id_range() {
+    let mut scripts = vec![];
+    for i in 1..60 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("select where id >= 10 and id < 50 and id > 12 and id <= 15".to_owned());
+    scripts.push("select where id > 57".to_owned());
+    scripts.push("select where id < 2".to_owned());
+    scripts.push("select where id > 5 and id < 3".to_owned());
+    scripts.push("select where id ! 3".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_defaults(scripts);
+
+    let row = |i: u32| {
+        format!("Row {{ id: {i}, username: \"user{i}\", email: \"person{i}@example.com\" }}")
+    };
+    result_match(
+        results[59..].to_vec(),
+        vec![
+            format!("csquarelite> {}", row(13)),
+            row(14),
+            row(15),
+            "Executed.".to_owned(),
+            format!("csquarelite> {}", row(58)),
+            row(59),
+            "Executed.".to_owned(),
+            format!("csquarelite> {}", row(1)),
+            "Executed.".to_owned(),
+            "csquarelite> Executed.".to_owned(),
+            "csquarelite> Syntax Error: unknown operator '!'".to_owned(),
+            "csquarelite> ".to_owned(),
+        ],
+    );
+}
+
+#[test]
+fn deletes_rows_in_an_id_range() {
+    let mut scripts = vec![];
+    for i in 1..40 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    scripts.push("delete where id > 3 and id <= 37".to_owned());
+    scripts.push("select".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_exec_with_defaults(scripts);
+
+    let row = |i: u32| {
+        format!("Row {{ id: {i}, username: \"user{i}\", email: \"person{i}@example.com\" }}")
+    };
+    result_match(
+        results[39..].to_vec(),
+        vec![
+            "csquarelite> Executed.".to_owned(),
+            format!("csquarelite> {}", row(1)),
+            row(2),
+            row(3),
+            row(38),
+            row(39),
+            "Executed.".to_owned(),
+            "csquarelite> ".to_owned(),
+        ],
+    );
+}
+
+#[test]
+fn works_with_a_cache_smaller_than_the_table() {
+    let mut scripts = vec![];
+    for i in (0..300).rev() {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    for i in (0..300).step_by(2) {
+        scripts.push(format!("delete where id = {i}"));
+    }
+    scripts.push("update set username = changed where id = 151".to_owned());
+    scripts.push("select".to_owned());
+    scripts.push(".exit".to_owned());
+    let results = run_script_in_memory(
+        scripts,
+        PagerConfig {
+            cache_size: 3,
+            ..PagerConfig::default()
+        },
+    );
+
+    let rows: Vec<&String> = results.iter().filter(|l| l.contains("Row {")).collect();
+    assert_eq!(rows.len(), 150);
+    assert!(rows[0].ends_with("Row { id: 1, username: \"user1\", email: \"person1@example.com\" }"));
+    assert_eq!(
+        rows[75],
+        "Row { id: 151, username: \"changed\", email: \"person151@example.com\" }"
+    );
+    assert_eq!(
+        rows[149],
+        "Row { id: 299, username: \"user299\", email: \"person299@example.com\" }"
+    );
+}
+
+#[test]
+fn commits_or_rolls_back_a_transaction() {
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "begin",
+        "insert 2 user2 person2@example.com",
+        "delete where id = 1",
+        "select",
+        "rollback",
+        "select",
+        "begin",
+        "insert 3 user3 person3@example.com",
+        "commit",
+        "select",
+        "commit",
+        "begin",
+        "begin",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 2, username: \"user2\", email: \"person2@example.com\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Row { id: 3, username: \"user3\", email: \"person3@example.com\" }",
+            "Executed.",
+            "csquarelite> Error: no transaction is active",
+            "csquarelite> Executed.",
+            "csquarelite> Error: cannot start a transaction within a transaction",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn undoes_a_failed_statement_without_ending_the_transaction() {
+    let email = "e".repeat(210);
+    let username = "u".repeat(32);
+    let scripts = vec![
+        "insert 1 user1 person1@example.com".to_string(),
+        format!("insert 2 user2 {email}"),
+        "begin".to_string(),
+        // row 1 is rewritten before row 2 turns out too long for a page
+        format!("update set username = {username} where id >= 1"),
+        "insert 3 user3 person3@example.com".to_string(),
+        "commit".to_string(),
+        "select".to_string(),
+        ".exit".to_string(),
+    ];
+    let results = run_script_exec_with_small_pages(scripts);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.".to_string(),
+            "csquarelite> Executed.".to_string(),
+            "csquarelite> Executed.".to_string(),
+            "csquarelite> Row too long: 254 bytes, a page holds at most 242.".to_string(),
+            "csquarelite> Executed.".to_string(),
+            "csquarelite> Executed.".to_string(),
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }"
+                .to_string(),
+            format!("Row {{ id: 2, username: \"user2\", email: \"{email}\" }}"),
+            "Row { id: 3, username: \"user3\", email: \"person3@example.com\" }".to_string(),
+            "Executed.".to_string(),
+        ],
+    );
+}
+
+#[test]
+fn rolls_back_a_transaction_larger_than_the_cache() {
+    for journal_mode in ["delete", "wal"] {
+        let db_filename = gen_random_filename();
+        let mut scripts = vec!["insert 1 user1 person1@example.com".to_owned()];
+        scripts.push("begin".to_owned());
+        for i in 2..300 {
+            scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+        }
+        scripts.push("rollback".to_owned());
+        scripts.push("select".to_owned());
+        scripts.push(".exit".to_owned());
+        let results = run_script_exec_with_args(
+            scripts,
+            Some(db_filename.to_owned()),
+            false,
+            &["--cache-size", "3", "--journal-mode", journal_mode],
+        );
+
+        assert_eq!(
+            results[301],
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }"
+        );
+        assert_eq!(results[302], "Executed.");
+        assert_eq!(results[303], "csquarelite> ");
+
+        // the rolled back pages are gone from disk too, also after the log is checkpointed
+        let scripts = vec!["select", ".exit"];
+        let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+        result_match(
+            results,
+            vec![
+                "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+                "Executed.",
+                "csquarelite> ",
+            ],
+        );
+        remove_file(db_filename).unwrap();
+    }
+}
+
+#[test]
+fn keeps_the_page_size_a_database_was_created_with() {
+    for journal_mode in ["delete", "wal"] {
+        let db_filename = gen_random_filename();
+        let mut scripts: Vec<_> = (1..=30)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        scripts.push(".exit".to_owned());
+        run_script_exec_with_args(
+            scripts,
+            Some(db_filename.to_owned()),
+            false,
+            &["--page-size", "1024", "--journal-mode", journal_mode],
+        );
+
+        // reopened with the default page size, which a new database would get
+        let scripts = vec!["select", ".exit"];
+        let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+        assert_eq!(
+            results[0],
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }"
+        );
+        assert_eq!(
+            results[29],
+            "Row { id: 30, username: \"user30\", email: \"person30@example.com\" }"
+        );
+        assert_eq!(results[30], "Executed.");
+
+        // the page size follows the magic string and format version in the header
+        let bytes = read(&db_filename).unwrap();
+        assert_eq!(bytes.len() % 1024, 0);
+        assert_eq!(bytes[20..24], 1024u32.to_be_bytes());
+        remove_file(db_filename).unwrap();
+    }
+}
+
+#[test]
+fn keeps_commits_made_with_syncing_relaxed() {
+    for journal_mode in ["delete", "wal"] {
+        for synchronous in ["off", "normal"] {
+            let db_filename = gen_random_filename();
+            let scripts = vec![
+                "insert 1 user1 person1@example.com",
+                "begin",
+                "insert 2 user2 person2@example.com",
+                "rollback",
+                ".exit",
+            ];
+            run_script_exec_with_args(
+                scripts,
+                Some(db_filename.to_owned()),
+                false,
+                &["--journal-mode", journal_mode, "--synchronous", synchronous],
+            );
+
+            let scripts = vec!["select", ".exit"];
+            let results = run_script_exec(scripts, Some(db_filename.to_owned()), true);
+            result_match(
+                results,
+                vec![
+                    "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+                    "Executed.",
+                    "csquarelite> ",
+                ],
+            );
+        }
+    }
+}
+
+#[test]
+fn locks_out_other_writers_until_a_transaction_ends() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    let mut writer = spawn_session(&db_filename, &[]);
+    let mut writer_stdin = writer.stdin.take().unwrap();
+    let mut writer_stdout = BufReader::new(writer.stdout.take().unwrap());
+    writer_stdin
+        .write_all(b"begin\ninsert 2 user2 person2@example.com\n")
+        .unwrap();
+    for _ in 0..2 {
+        let mut line = String::new();
+        writer_stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "csquarelite> Executed.\n");
+    }
+
+    // readers go on seeing what was last committed
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+
+    let scripts = vec!["insert 3 user3 person3@example.com", "select", ".exit"];
+    let results = run_script_exec(scripts.clone(), Some(db_filename.to_owned()), false);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Error: database is locked",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+
+    // a writer that is willing to wait gets in once the transaction commits
+    let waiting_filename = db_filename.to_owned();
+    let waiting_writer = thread::spawn(move || {
+        run_script_exec_with_args(
+            scripts,
+            Some(waiting_filename),
+            false,
+            &["--busy-timeout", "30000"],
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+    writer_stdin.write_all(b"commit\n.exit\n").unwrap();
+    drop(writer_stdin);
+    writer.wait().unwrap();
+
+    result_match(
+        waiting_writer.join().unwrap(),
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Row { id: 2, username: \"user2\", email: \"person2@example.com\" }",
+            "Row { id: 3, username: \"user3\", email: \"person3@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn parses_a_statement_again_when_the_schema_changes_while_it_waits() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    let mut writer = spawn_session(&db_filename, &[]);
+    let mut writer_stdin = writer.stdin.take().unwrap();
+    let mut writer_stdout = BufReader::new(writer.stdout.take().unwrap());
+    writer_stdin
+        .write_all(b"begin\ncreate table t (id integer, n integer)\n")
+        .unwrap();
+    for _ in 0..2 {
+        let mut line = String::new();
+        writer_stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "csquarelite> Executed.\n");
+    }
+
+    // both statements are parsed before the table is committed and run after it
+    let waiting_filename = db_filename.to_owned();
+    let waiting_writer = thread::spawn(move || {
+        run_script_exec_with_args(
+            vec!["insert 2 user2 person2@example.com", ".exit"],
+            Some(waiting_filename),
+            false,
+            &["--busy-timeout", "30000"],
+        )
+    });
+    let waiting_filename = db_filename.to_owned();
+    let creating_writer = thread::spawn(move || {
+        run_script_exec_with_args(
+            vec!["create table t (id integer)", ".exit"],
+            Some(waiting_filename),
+            false,
+            &["--busy-timeout", "30000"],
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+    writer_stdin.write_all(b"commit\n.exit\n").unwrap();
+    drop(writer_stdin);
+    writer.wait().unwrap();
+
+    result_match(
+        waiting_writer.join().unwrap(),
+        vec!["csquarelite> Executed.", "csquarelite> "],
+    );
+    result_match(
+        creating_writer.join().unwrap(),
+        vec![
+            "csquarelite> Error: table t already exists",
+            "csquarelite> ",
+        ],
+    );
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn rolls_back_to_and_releases_savepoints() {
+    let scripts = vec![
+        "savepoint a",
+        "begin",
+        "insert 1 user1 person1@example.com",
+        "savepoint a",
+        "insert 2 user2 person2@example.com",
+        "savepoint b",
+        "insert 3 user3 person3@example.com",
+        "rollback to b",
+        "select",
+        "update set username = changed where id = 1",
+        "release savepoint b",
+        "release b",
+        "rollback to savepoint a",
+        "select",
+        "commit",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Error: no transaction is active",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Row { id: 2, username: \"user2\", email: \"person2@example.com\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Error: no such savepoint: b",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn rolls_back_to_a_savepoint_across_page_splits() {
+    for journal_mode in [JournalMode::Delete, JournalMode::Wal] {
+        let mut scripts = vec!["begin".to_owned()];
+        for i in 0..50 {
+            scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+        }
+        scripts.push("savepoint batch".to_owned());
+        for i in 50..300 {
+            scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+        }
+        scripts.push("delete where id < 25".to_owned());
+        scripts.push("rollback to batch".to_owned());
+        scripts.push("insert 300 user300 person300@example.com".to_owned());
+        scripts.push("commit".to_owned());
+        scripts.push("select".to_owned());
+        scripts.push(".exit".to_owned());
+        let results = run_script_in_memory(
+            scripts,
+            PagerConfig {
+                cache_size: 3,
+                journal_mode,
+                ..PagerConfig::default()
+            },
+        );
+        let ids: Vec<String> = results
+            .iter()
+            .filter_map(|l| l.split("id: ").nth(1))
+            .map(|l| l.split(',').next().unwrap().to_owned())
+            .collect();
+        let mut expected: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+        expected.push("300".to_owned());
+        assert_eq!(ids, expected);
+    }
+}
+
+#[test]
+fn rolls_back_a_hot_journal_left_by_a_crashed_session() {
+    let db_filename = gen_random_filename();
+    let journal_filename = format!("{db_filename}-journal");
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "insert 2 user2 person2@example.com",
+        ".exit",
+    ];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    let file_len = metadata(&db_filename).unwrap().len();
+
+    // with a tiny cache, evicted pages reach the file before the transaction is committed;
+    // the session then ends without `.exit`, as if the process had been killed
+    let mut scripts = vec!["begin".to_owned()];
+    for i in 3..300 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--cache-size", "3"],
+    );
+    assert!(metadata(&journal_filename).is_ok());
+    assert!(metadata(&db_filename).unwrap().len() > file_len);
+
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Row { id: 2, username: \"user2\", email: \"person2@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+    assert!(metadata(&journal_filename).is_err());
+    assert_eq!(metadata(&db_filename).unwrap().len(), file_len);
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn keeps_commits_in_the_wal_until_a_checkpoint() {
+    let db_filename = gen_random_filename();
+    let wal_filename = format!("{db_filename}-wal");
+    let wal = &["--journal-mode", "wal"];
+    let rows = vec![
+        "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+        "Row { id: 2, username: \"user2\", email: \"person2@example.com\" }",
+        "Executed.",
+    ];
+
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "insert 2 user2 person2@example.com",
+        ".exit",
+    ];
+    run_script_exec_with_args(scripts, Some(db_filename.to_owned()), false, wal);
+    assert_eq!(metadata(&db_filename).unwrap().len(), 0);
+    assert!(metadata(&wal_filename).unwrap().len() > 0);
+
+    let scripts = vec!["select", ".checkpoint", ".exit"];
+    let results = run_script_exec_with_args(scripts, Some(db_filename.to_owned()), false, wal);
+    result_match(results, rows.clone());
+    assert_eq!(metadata(&db_filename).unwrap().len(), 2 * 4096);
+    assert_eq!(metadata(&wal_filename).unwrap().len(), 20);
+
+    // opening without WAL mode folds whatever is left in the log into the file
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    result_match(results, rows);
+    assert!(metadata(&wal_filename).is_err());
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn ignores_wal_frames_that_were_never_committed() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--journal-mode", "wal"],
+    );
+
+    // evicted pages are spilled to the log, but the session ends before committing them
+    let mut scripts = vec!["begin".to_owned()];
+    for i in 2..300 {
+        scripts.push(format!("insert {i} user{i} person{i}@example.com"));
+    }
+    run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--journal-mode", "wal", "--cache-size", "3"],
+    );
+
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--journal-mode", "wal"],
+    );
+
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+    remove_file(format!("{db_filename}-wal")).unwrap();
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn lets_readers_in_while_a_wal_transaction_writes() {
+    let db_filename = gen_random_filename();
+    let wal = &["--journal-mode", "wal"];
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec_with_args(scripts, Some(db_filename.to_owned()), false, wal);
+
+    // the small cache spills uncommitted pages to the log while the transaction is open
+    let mut writer = spawn_session(
+        &db_filename,
+        &["--journal-mode", "wal", "--cache-size", "3"],
+    );
+    let mut writer_stdin = writer.stdin.take().unwrap();
+    let mut writer_stdout = BufReader::new(writer.stdout.take().unwrap());
+    writer_stdin.write_all(b"begin\n").unwrap();
+    for i in 2..200 {
+        writer_stdin
+            .write_all(format!("insert {i} user{i} person{i}@example.com\n").as_bytes())
+            .unwrap();
+    }
+    for _ in 1..200 {
+        let mut line = String::new();
+        writer_stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "csquarelite> Executed.\n");
+    }
+
+    let scripts = vec!["select", ".exit"];
+    let results =
+        run_script_exec_with_args(scripts.clone(), Some(db_filename.to_owned()), false, wal);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+
+    writer_stdin.write_all(b"commit\n.exit\n").unwrap();
+    drop(writer_stdin);
+    writer.wait().unwrap();
+
+    let results = run_script_exec_with_args(scripts, Some(db_filename.to_owned()), false, wal);
+    let mut expected: Vec<String> = (1..200)
+        .map(|i| {
+            format!("Row {{ id: {i}, username: \"user{i}\", email: \"person{i}@example.com\" }}")
+        })
+        .collect();
+    expected[0] = format!("csquarelite> {}", expected[0]);
+    expected.push("Executed.".to_owned());
+    expected.push("csquarelite> ".to_owned());
+    result_match(results, expected);
+    remove_file(format!("{db_filename}-wal")).unwrap();
+    remove_file(db_filename).unwrap();
+}
+
+#[test]
+fn reports_a_corrupt_page_instead_of_crashing() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    // flip a byte inside the root leaf (page 1) so its checksum no longer matches
+    let mut bytes = read(&db_filename).unwrap();
+    bytes[4096 + 100] ^= 0xff;
+    write(&db_filename, bytes).unwrap();
+
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename), true);
+
+    assert!(results[0]
+        .starts_with("csquarelite> Error: database file is corrupt at page 1: checksum mismatch"));
+    assert_eq!(results[1], "csquarelite> ");
+}
+
+#[test]
+fn refuses_a_file_that_is_not_a_whole_number_of_pages() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    let mut bytes = read(&db_filename).unwrap();
+    bytes.extend_from_slice(&[0; 10]);
+    write(&db_filename, bytes).unwrap();
+
+    let scripts = vec!["select", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename), true);
+
+    assert_eq!(
+        results[0],
+        "Error: database file is corrupt at page 2: file size 8202 is not a whole number of pages"
+    );
+}
+
+#[test]
+fn refuses_a_file_that_is_not_a_database() {
+    let db_filename = gen_random_filename();
+    write(&db_filename, vec![b'x'; 8192]).unwrap();
+
+    let results = run_script_exec(vec!["select", ".exit"], Some(db_filename), true);
+
+    assert_eq!(results[0], "Error: file is not a database");
+}
+
+#[test]
+fn refuses_a_file_with_a_newer_format_version() {
+    let db_filename = gen_random_filename();
+    let scripts = vec!["insert 1 user1 person1@example.com", ".exit"];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    // the format version follows the 16 byte magic string in the header
+    let mut bytes = read(&db_filename).unwrap();
+    bytes[16..20].copy_from_slice(&4u32.to_be_bytes());
+    write(&db_filename, bytes).unwrap();
+
+    let results = run_script_exec(vec!["select", ".exit"], Some(db_filename), true);
+
+    assert_eq!(
+        results[0],
+        "Error: unsupported database format: format version 4 is newer than version 3"
+    );
+}
+
+#[test]
+fn creates_a_table_with_its_own_columns() {
+    let db_filename = gen_random_filename();
+    let scripts = vec![
+        "create table orders (num integer, item text(8), qty integer)",
+        "insert into orders 2 pear 4",
+        "insert into orders 1 apple 3",
+        "insert into orders 3.5 cherry 1",
+        "update orders set qty = 10 where num = 1",
+        "select from orders where id = 1",
+        "create table orders (num integer)",
+        ".exit",
+    ];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Validation Error: Integer value for 'num' is not a valid integer",
+            "csquarelite> Executed.",
+            "csquarelite> Syntax Error: expected 'where num <op> <num>'",
+            "csquarelite> Error: table orders already exists",
+            "csquarelite> ",
+        ],
+    );
+
+    // the definition is read back from the catalog when the database is opened again
+    let scripts = vec![
+        "insert into orders 3 cherrytree 1",
+        "select from orders",
+        ".exit",
+    ];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), true);
+    result_match(
+        results,
+        vec![
+            "csquarelite> String value for 'item' too long.",
+            "csquarelite> Row { num: 1, item: \"apple\", qty: 10 }",
+            "Row { num: 2, item: \"pear\", qty: 4 }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn keeps_the_users_table_when_a_table_is_created_first() {
+    let db_filename = gen_random_filename();
+    let scripts = vec![
+        "create table orders (num integer, item text(8))",
+        "insert into orders 1 apple",
+        "insert into users 1 user1 person1@example.com",
+        ".exit",
+    ];
+    run_script_exec(scripts, Some(db_filename.to_owned()), false);
+
+    let scripts = vec!["select from users", "select from orders", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), true);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> Row { num: 1, item: \"apple\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn works_on_the_users_table_when_no_table_is_named() {
+    let scripts = vec![
+        "create table orders (num integer, item text(8))",
+        "insert 1 a b",
+        "select",
+        "select from orders",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"a\", email: \"b\" }",
+            "Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn stores_values_of_every_type_by_column_affinity() {
+    let db_filename = gen_random_filename();
+    let scripts = vec![
+        "create table t (id integer, n integer, r real, s text, b blob, v)",
+        "insert into t 1 -5000000000 1.5 hello x'00ff' null",
+        "insert into t 2 3.0 2 42 'it''s' 7",
+        "insert into t 3 abc 1e3 2.5 12 'x'",
+        "insert into t 4 1 1 1 x'0g' 1",
+        "insert into t 4294967296 1 1 1 1 1",
+        "update t set r = null, s = 99999999999999999999 where id = 2",
+        ".exit",
+    ];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), false);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Validation Error: Invalid value x'0g' for 'b'",
+            "csquarelite> Validation Error: Integer value for 'id' is too large for a key",
+            "csquarelite> Executed.",
+            "csquarelite> ",
+        ],
+    );
+
+    // every value reads back as the storage class it was stored as
+    let scripts = vec!["select from t", ".exit"];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), true);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Row { id: 1, n: -5000000000, r: 1.5, s: \"hello\", b: x'00ff', v: NULL }",
+            "Row { id: 2, n: 3, r: NULL, s: \"1e20\", b: \"it's\", v: 7 }",
+            "Row { id: 3, n: \"abc\", r: 1000.0, s: \"2.5\", b: 12, v: \"x\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn refuses_keys_outside_the_32_bit_range() {
+    let scripts = vec![
+        "create table t (id integer, n integer)",
+        "insert into t -1 5",
+        "insert into t 5000000000 5",
+        "insert into t 4294967295 5",
+        "select from t where id > 4294967294",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Validation Error: Integer value for 'id' cannot be negative",
+            "csquarelite> Validation Error: Integer value for 'id' is too large for a key",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 4294967295, n: 5 }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn keeps_spaces_and_commas_inside_quoted_text() {
+    let scripts = vec![
+        "create table notes (id integer, title text, body text)",
+        "insert into notes 1 'hello world' 'a, b and c'",
+        "insert into notes 2 'x = 1' plain",
+        "update notes set title = 'two words', body = 'one, two' where id = 2",
+        "insert into notes 3 a b",
+        "update notes set body='it''s, here',title='=' where id>2 and id<= 3",
+        "insert into notes 4 'open quote",
+        "select from notes where id <= '3'",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Validation Error: Invalid value 'open quote for 'title'",
+            "csquarelite> Row { id: 1, title: \"hello world\", body: \"a, b and c\" }",
+            "Row { id: 2, title: \"two words\", body: \"one, two\" }",
+            "Row { id: 3, title: \"=\", body: \"it's, here\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn refuses_invalid_table_definitions() {
+    let scripts = vec![
+        "create table t (name text(8), id integer)",
+        "create table t (id integer, id integer)",
+        "create table t (id integer, data text(0))",
+        "create table t (id integer, price decimal(10,2,1))",
+        "create table t id integer",
+        "create table users (id integer)",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Validation Error: Key column 'name' must be an integer",
+            "csquarelite> Validation Error: Duplicate column 'id'",
+            "csquarelite> Validation Error: Unknown type 'text(0)' for column 'data'",
+            "csquarelite> Validation Error: Unknown type 'decimal(10,2,1)' for column 'price'",
+            "csquarelite> Syntax Error: expected 'create table <name> (<column> <type>, ...)'",
+            "csquarelite> Error: table users already exists",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn takes_a_precision_and_scale_in_column_types() {
+    let scripts = vec![
+        "create table prices (id integer, price decimal(10,2), qty numeric(5, 0), code text(3))",
+        "insert into prices 1 9.99 '3' abc",
+        "insert into prices 2 10.0 4.5 abcd",
+        "insert into prices 2 10.0 4.5 xyz",
+        "select from prices where id >= 1",
+        ".exit",
+    ];
+    let results = run_script_exec_with_defaults(scripts);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> String value for 'code' too long.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, price: 9.99, qty: 3, code: \"abc\" }",
+            "Row { id: 2, price: 10, qty: 4.5, code: \"xyz\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn keeps_rows_of_several_tables_apart() {
+    let db_filename = gen_random_filename();
+    let mut scripts = vec![
+        "insert 1 user1 person1@example.com".to_string(),
+        "create table orders (num integer, item text(8))".to_string(),
+        "create table items (id integer, name text(10), price integer)".to_string(),
+    ];
+    // enough rows for every table to split its root
+    for i in 1..=40 {
+        scripts.push(format!("insert into orders {i} order{i}"));
+        scripts.push(format!("insert into items {i} item{i} {i}"));
+    }
+    scripts.push("insert into stock 1 2".to_string());
+    scripts.push(".exit".to_string());
+    let results = run_script_exec_with_args(
+        scripts,
+        Some(db_filename.to_owned()),
+        false,
+        &["--page-size", "512"],
+    );
+    assert!(results[..results.len() - 2]
+        .iter()
+        .all(|line| line == "csquarelite> Executed."));
+    assert_eq!(
+        results[results.len() - 2],
+        "csquarelite> Validation Error: Unknown table 'stock'"
+    );
+
+    let scripts = vec![
+        "update items set price = 100 where id = 2",
+        "delete from orders where num > 1",
+        "select",
+        "select from orders",
+        "select from items where id <= 2",
+        ".exit",
+    ];
+    let results = run_script_exec(scripts, Some(db_filename.to_owned()), true);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> Row { num: 1, item: \"order1\" }",
+            "Executed.",
+            "csquarelite> Row { id: 1, name: \"item1\", price: 1 }",
+            "Row { id: 2, name: \"item2\", price: 100 }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+}
+
+#[test]
+fn prints_an_error_message_on_duplicate_id() {
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "insert 1 user2 person2@example.com",
+        "select",
+        ".exit",
+    ];
     let results = run_script_exec_with_defaults(scripts);
 
-    assert_eq!(results[results.len() - 2], "csquarelite> Error: Table Full")
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Error: Duplicate key",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
 }
 
 #[test]
@@ -109,3 +1561,91 @@ fn keeps_data_after_closing_connection() {
         ],
     );
 }
+
+#[test]
+fn keeps_an_in_memory_database_off_disk() {
+    let scripts = vec![
+        "insert 1 user1 person1@example.com",
+        "begin",
+        "insert 2 user2 person2@example.com",
+        "rollback",
+        "select",
+        ".exit",
+    ];
+    let results = run_script_exec(scripts, Some(":memory:".to_string()), false);
+    result_match(
+        results,
+        vec![
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Executed.",
+            "csquarelite> Row { id: 1, username: \"user1\", email: \"person1@example.com\" }",
+            "Executed.",
+            "csquarelite> ",
+        ],
+    );
+    assert!(metadata(":memory:").is_err());
+
+    // every connection gets a database of its own
+    let results = run_script_exec(vec!["select", ".exit"], Some(":memory:".to_string()), false);
+    result_match(results, vec!["csquarelite> Executed.", "csquarelite> "]);
+}
+
+#[test]
+fn runs_queries_on_a_database_opened_in_process() {
+    let mut db = Database::try_new(MEMORY_DB_FILENAME).unwrap();
+    let mut output = vec![];
+    for query in [
+        "insert 1 user1 person1@example.com",
+        "insert 1 user1 person1@example.com",
+        "select",
+    ] {
+        assert!(matches!(
+            db.handle_query_to(query, &mut output),
+            Ok(HandleDBQueryStatusCode::Continue)
+        ));
+    }
+    assert!(matches!(
+        db.handle_query_to(".exit", &mut output),
+        Ok(HandleDBQueryStatusCode::Exit)
+    ));
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Executed.\nError: Duplicate key\n\
+         Row { id: 1, username: \"user1\", email: \"person1@example.com\" }\nExecuted.\n"
+    );
+}
+
+#[test]
+fn fails_a_query_whose_output_cannot_be_written() {
+    struct ClosedOutput;
+
+    impl Write for ClosedOutput {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut db = Database::try_new(MEMORY_DB_FILENAME).unwrap();
+    let mut output = vec![];
+    db.handle_query_to("insert 1 user1 person1@example.com", &mut output)
+        .unwrap();
+
+    let result = db.handle_query_to("select", &mut ClosedOutput);
+    assert!(
+        matches!(result, Err(DatabaseError::OutputError(e)) if e.kind() == io::ErrorKind::BrokenPipe)
+    );
+
+    // the database is still usable afterwards
+    db.handle_query_to("select", &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Executed.\nRow { id: 1, username: \"user1\", email: \"person1@example.com\" }\nExecuted.\n"
+    );
+}
@@ -1,31 +1,54 @@
+use cstack_sqlite::{
+    db::{Database, DatabaseError, HandleDBQueryStatusCode, MEMORY_DB_FILENAME},
+    pager::PagerConfig,
+};
 use rand::Rng;
 use std::{
     fs::remove_file,
     io::Write,
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
 };
 
+const BIN: &str = env!("CARGO_BIN_EXE_cstack_sqlite");
+const PROMPT: &str = "csquarelite> ";
+
+/// A path for a new database in the target directory, which tests remove once done.
 pub fn gen_random_filename() -> String {
     let rnd = rand::thread_rng().gen_range(10000..10000000);
-    format!("{rnd}-stackqlite.db")
+    format!("{}/{rnd}-stackqlite.db", env!("CARGO_TARGET_TMPDIR"))
+}
+
+/// Starts a session on `filename` that stays open, for tests that need two connections to
+/// the same database at once. Scripts are written to its stdin as the test goes.
+pub fn spawn_session(filename: &str, args: &[&str]) -> Child {
+    Command::new(BIN)
+        .args(["--filename", filename])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap()
 }
 
 pub fn run_script_exec<T: ToString>(
     scripts: Vec<T>,
     filename: Option<String>,
     cleanup_db: bool,
+) -> Vec<String> {
+    run_script_exec_with_args(scripts, filename, cleanup_db, &[])
+}
+
+pub fn run_script_exec_with_args<T: ToString>(
+    scripts: Vec<T>,
+    filename: Option<String>,
+    cleanup_db: bool,
+    args: &[&str],
 ) -> Vec<String> {
     let db_filename = filename.unwrap_or(gen_random_filename());
 
-    let mut child = Command::new("cargo")
-        .args([
-            "run",
-            "-p",
-            "cstack_sqlite",
-            "--",
-            "--filename",
-            db_filename.as_str(),
-        ])
+    let mut child = Command::new(BIN)
+        .args(["--filename", db_filename.as_str()])
+        .args(args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
@@ -33,9 +56,13 @@ pub fn run_script_exec<T: ToString>(
 
     if let Some(mut stdin) = child.stdin.take() {
         for script in scripts {
-            stdin
+            // a session that fails to open exits without reading its script
+            if stdin
                 .write_all(&[script.to_string().as_bytes(), b"\n"].concat())
-                .unwrap();
+                .is_err()
+            {
+                break;
+            }
         }
     }
 
@@ -50,8 +77,51 @@ pub fn run_script_exec<T: ToString>(
     result.split("\n").map(|s| s.to_owned()).collect()
 }
 
+/// Runs `scripts` against an in-memory database opened in this process, for tests that
+/// never reopen it. The output reads like a session's, prompts included.
+pub fn run_script_in_memory<T: ToString>(scripts: Vec<T>, config: PagerConfig) -> Vec<String> {
+    let mut output = vec![];
+    if let Err(e) = run_session(scripts, config, &mut output) {
+        writeln!(output, "Error: {e}").unwrap();
+    }
+
+    let result = String::from_utf8(output).unwrap();
+    result.split("\n").map(|s| s.to_owned()).collect()
+}
+
+fn run_session<T: ToString>(
+    scripts: Vec<T>,
+    config: PagerConfig,
+    output: &mut Vec<u8>,
+) -> Result<(), DatabaseError> {
+    let mut db = Database::try_new_with_config(MEMORY_DB_FILENAME, config)?;
+
+    for script in scripts {
+        output.extend_from_slice(PROMPT.as_bytes());
+        if let HandleDBQueryStatusCode::Exit = db.handle_query_to(&script.to_string(), output)? {
+            return Ok(());
+        }
+    }
+    // the session waits for more input once the script runs out
+    output.extend_from_slice(PROMPT.as_bytes());
+
+    Ok(())
+}
+
 pub fn run_script_exec_with_defaults<T: ToString>(scripts: Vec<T>) -> Vec<String> {
-    run_script_exec(scripts, None, true)
+    run_script_in_memory(scripts, PagerConfig::default())
+}
+
+/// Like `run_script_exec_with_defaults`, with 512 byte pages that a dozen rows fill up, for
+/// tests on the shape of the tree.
+pub fn run_script_exec_with_small_pages<T: ToString>(scripts: Vec<T>) -> Vec<String> {
+    run_script_in_memory(
+        scripts,
+        PagerConfig {
+            page_size: 512,
+            ..PagerConfig::default()
+        },
+    )
 }
 
 pub fn result_match<R: ToString, T: ToString>(result: Vec<R>, expected: Vec<T>) {